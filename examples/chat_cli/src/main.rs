@@ -1,6 +1,6 @@
 use dotenvy::dotenv;
 use openai::{
-    chat::{ChatCompletion, ChatCompletionMessage, ChatCompletionMessageRole},
+    chat::{Conversation, Truncation},
     set_key,
 };
 use std::{
@@ -14,11 +14,9 @@ async fn main() {
     dotenv().unwrap();
     set_key(env::var("OPENAI_KEY").unwrap());
 
-    let mut messages = vec![ChatCompletionMessage {
-        role: ChatCompletionMessageRole::System,
-        content: "You are a large language model built into a command line interface as an example of what the `openai` Rust library made by Valentine Briese can do.".to_string(),
-        name: None,
-    }];
+    let mut conversation = Conversation::new("gpt-3.5-turbo")
+        .system("You are a large language model built into a command line interface as an example of what the `openai` Rust library made by Valentine Briese can do.")
+        .truncation(Truncation::Summarize { keep_last: 10 });
 
     loop {
         print!("User: ");
@@ -27,25 +25,13 @@ async fn main() {
         let mut user_message_content = String::new();
 
         stdin().read_line(&mut user_message_content).unwrap();
-        messages.push(ChatCompletionMessage {
-            role: ChatCompletionMessageRole::User,
-            content: user_message_content,
-            name: None,
-        });
 
-        let chat_completion = ChatCompletion::builder("gpt-3.5-turbo", messages.clone())
-            .create()
-            .await
-            .unwrap()
-            .unwrap();
-        let returned_message = chat_completion.choices.first().unwrap().message.clone();
+        let returned_message = conversation.send(user_message_content).await.unwrap();
 
         println!(
             "{:#?}: {}",
             &returned_message.role,
-            &returned_message.content.trim()
+            returned_message.content.as_deref().unwrap_or_default().trim()
         );
-
-        messages.push(returned_message);
     }
 }
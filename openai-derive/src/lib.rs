@@ -0,0 +1,524 @@
+//! Derive macros for the [`openai`](https://docs.rs/openai) crate, enabled with its `derive` feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    meta::ParseNestedMeta, parse_macro_input, parse_quote, spanned::Spanned, Attribute, Data,
+    DataEnum, DataStruct, DeriveInput, Expr, Fields, Generics, Lit, LitStr, Meta,
+};
+
+/// Implements `OpenAiTool` and `ToolParameter` for a struct with named fields,
+/// so it can be given to the model as a function and read back from the arguments of its calls.
+///
+/// The function is named after the struct in snake case, unless named with `#[tool(name = "...")]`,
+/// and is described by the doc comment of the struct. The doc comments of fields describe the parameters.
+/// Fields are required unless they are an `Option` or have `#[serde(default)]`,
+/// and `#[serde(rename = "...")]` and `#[serde(rename_all = "...")]` are taken into account,
+/// as are their `deserialize = "..."` forms, since the arguments of calls are deserialized.
+/// `#[serde(flatten)]` and renaming with only `serialize = "..."` aren't supported and fail to compile.
+/// Type parameters must implement `ToolParameter`.
+///
+/// ## Examples
+///
+/// ```rust
+/// use openai::chat::OpenAiTool;
+/// use serde::Deserialize;
+///
+/// /// Gets the current weather of a city.
+/// #[derive(Deserialize, OpenAiTool)]
+/// struct GetWeather {
+///     /// The name of the city, such as "Paris".
+///     city: String,
+///     unit: Option<String>,
+/// }
+///
+/// let tool = GetWeather::tool();
+///
+/// assert_eq!(tool.function.name, "get_weather");
+/// assert_eq!(tool.function.description.as_deref(), Some("Gets the current weather of a city."));
+/// assert_eq!(GetWeather::from_arguments(r#"{"city":"Paris"}"#).unwrap().city, "Paris");
+/// ```
+#[proc_macro_derive(OpenAiTool, attributes(tool))]
+pub fn derive_openai_tool(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand_openai_tool(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Implements `ToolParameter` for a struct with named fields or an enum whose variants have no fields,
+/// so it can be the type of a field of an `OpenAiTool`.
+///
+/// Structs are described the same way as by `#[derive(OpenAiTool)]`,
+/// and enums are described as one of the names of their variants.
+#[proc_macro_derive(ToolParameter)]
+pub fn derive_tool_parameter(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand_tool_parameter(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_openai_tool(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.ident.span(),
+            "`OpenAiTool` can only be derived for structs",
+        ));
+    };
+    let ident = &input.ident;
+    let mut generics = bounded_generics(&input.generics);
+
+    generics
+        .make_where_clause()
+        .predicates
+        .push(parse_quote!(Self: ::openai::__private::serde::de::DeserializeOwned));
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let name = match tool_name(&input.attrs)? {
+        Some(name) => name,
+        None => rename_variant(&ident.to_string(), "snake_case").unwrap_or_default(),
+    };
+    let description = match doc_comment(&input.attrs) {
+        Some(description) => quote!(::std::option::Option::Some(#description)),
+        None => quote!(::std::option::Option::None),
+    };
+    let schema = struct_schema(input, data)?;
+
+    Ok(quote! {
+        impl #impl_generics ::openai::chat::ToolParameter for #ident #ty_generics #where_clause {
+            fn schema() -> ::openai::__private::serde_json::Value {
+                #schema
+            }
+        }
+
+        impl #impl_generics ::openai::chat::OpenAiTool for #ident #ty_generics #where_clause {
+            const NAME: &'static str = #name;
+            const DESCRIPTION: ::std::option::Option<&'static str> = #description;
+        }
+    })
+}
+
+fn expand_tool_parameter(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    let generics = bounded_generics(&input.generics);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let schema = match &input.data {
+        Data::Struct(data) => struct_schema(input, data)?,
+        Data::Enum(data) => enum_schema(input, data)?,
+        Data::Union(_) => {
+            return Err(syn::Error::new(
+                ident.span(),
+                "`ToolParameter` cannot be derived for unions",
+            ))
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::openai::chat::ToolParameter for #ident #ty_generics #where_clause {
+            fn schema() -> ::openai::__private::serde_json::Value {
+                #schema
+            }
+        }
+    })
+}
+
+/// Returns `generics` with a `ToolParameter` bound on each type parameter, which the schemas of the fields need.
+fn bounded_generics(generics: &Generics) -> Generics {
+    let mut generics = generics.clone();
+    let params: Vec<_> = generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .collect();
+    let where_clause = generics.make_where_clause();
+
+    for param in params {
+        where_clause
+            .predicates
+            .push(parse_quote!(#param: ::openai::chat::ToolParameter));
+    }
+
+    generics
+}
+
+/// Returns an expression building the schema of an object with the fields of `data`.
+fn struct_schema(input: &DeriveInput, data: &DataStruct) -> syn::Result<TokenStream2> {
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new(
+            input.ident.span(),
+            "only structs with named fields can be described as objects",
+        ));
+    };
+    let container = SerdeAttributes::parse(&input.attrs)?;
+    let mut properties = Vec::new();
+
+    for field in &fields.named {
+        let attributes = SerdeAttributes::parse(&field.attrs)?;
+
+        if attributes.skip {
+            continue;
+        }
+
+        let ty = &field.ty;
+        let ident = field.ident.as_ref().expect("named fields have identifiers");
+
+        if attributes.flatten {
+            return Err(syn::Error::new(
+                field.span(),
+                "`#[serde(flatten)]` is not supported, the fields of the flattened type must be declared in this struct",
+            ));
+        }
+
+        let name = property_name(&ident.to_string(), &attributes, &container, rename_field)?;
+        let describe = doc_comment(&field.attrs).map(|description| {
+            quote! {
+                if let ::std::option::Option::Some(schema) = schema.as_object_mut() {
+                    schema.insert(
+                        "description".to_string(),
+                        ::openai::__private::serde_json::Value::from(#description),
+                    );
+                }
+            }
+        });
+        let required = if attributes.default || container.default {
+            quote!(false)
+        } else {
+            quote!(<#ty as ::openai::chat::ToolParameter>::REQUIRED)
+        };
+
+        properties.push(quote! {
+            let mut schema = <#ty as ::openai::chat::ToolParameter>::schema();
+
+            #describe
+            properties.insert(#name.to_string(), schema);
+
+            if #required {
+                required.push(::openai::__private::serde_json::Value::from(#name));
+            }
+        });
+    }
+
+    Ok(quote! {
+        #[allow(unused_mut)]
+        let mut properties = ::openai::__private::serde_json::Map::new();
+        #[allow(unused_mut)]
+        let mut required: ::std::vec::Vec<::openai::__private::serde_json::Value> =
+            ::std::vec::Vec::new();
+
+        #(#properties)*
+
+        ::openai::__private::serde_json::json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+            "additionalProperties": false,
+        })
+    })
+}
+
+/// Returns an expression building the schema of a string that is one of the variants of `data`.
+fn enum_schema(input: &DeriveInput, data: &DataEnum) -> syn::Result<TokenStream2> {
+    let container = SerdeAttributes::parse(&input.attrs)?;
+    let mut names = Vec::new();
+
+    for variant in &data.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(syn::Error::new(
+                variant.span(),
+                "only enums whose variants have no fields can be described",
+            ));
+        }
+
+        let attributes = SerdeAttributes::parse(&variant.attrs)?;
+
+        if attributes.skip {
+            continue;
+        }
+
+        names.push(property_name(
+            &variant.ident.to_string(),
+            &attributes,
+            &container,
+            rename_variant,
+        )?);
+    }
+
+    Ok(quote! {
+        ::openai::__private::serde_json::json!({
+            "type": "string",
+            "enum": [#(#names),*],
+        })
+    })
+}
+
+/// The `#[serde(...)]` attributes that change how a value is deserialized from the arguments of a call.
+#[derive(Default)]
+struct SerdeAttributes {
+    rename: Option<String>,
+    rename_all: Option<LitStr>,
+    default: bool,
+    skip: bool,
+    flatten: bool,
+}
+
+impl SerdeAttributes {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut attributes = SerdeAttributes::default();
+
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    attributes.rename = deserialized_name(&meta)?.map(|name| name.value());
+
+                    return Ok(());
+                } else if meta.path.is_ident("rename_all") {
+                    attributes.rename_all = deserialized_name(&meta)?;
+
+                    return Ok(());
+                } else if meta.path.is_ident("default") {
+                    attributes.default = true;
+                } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_deserializing") {
+                    attributes.skip = true;
+                } else if meta.path.is_ident("flatten") {
+                    attributes.flatten = true;
+                }
+
+                // Skip what the attributes we don't care about hold
+                if meta.input.peek(syn::Token![=]) {
+                    meta.value()?.parse::<Expr>()?;
+                } else if meta.input.peek(syn::token::Paren) {
+                    let content;
+
+                    syn::parenthesized!(content in meta.input);
+                    content.parse::<TokenStream2>()?;
+                }
+
+                Ok(())
+            })?;
+        }
+
+        Ok(attributes)
+    }
+}
+
+/// Returns the name given to `rename` or `rename_all` for deserializing,
+/// either as `rename = "..."` or as `rename(deserialize = "...")`.
+///
+/// A name given only for serializing fails, since leaving it out would silently describe other names than it.
+fn deserialized_name(meta: &ParseNestedMeta) -> syn::Result<Option<LitStr>> {
+    if meta.input.peek(syn::Token![=]) {
+        return Ok(Some(meta.value()?.parse()?));
+    }
+
+    let mut deserialize = None;
+    let mut serialize = false;
+
+    meta.parse_nested_meta(|nested| {
+        if nested.path.is_ident("deserialize") {
+            deserialize = Some(nested.value()?.parse()?);
+        } else if nested.path.is_ident("serialize") {
+            nested.value()?.parse::<LitStr>()?;
+            serialize = true;
+        } else {
+            return Err(nested.error("expected `serialize` or `deserialize`"));
+        }
+
+        Ok(())
+    })?;
+
+    if serialize && deserialize.is_none() {
+        return Err(meta.error(
+            "renaming only for serializing is not supported, the arguments of calls are deserialized, so give the name with `deserialize = \"...\"`",
+        ));
+    }
+
+    Ok(deserialize)
+}
+
+/// Returns the name given with `#[tool(name = "...")]`.
+fn tool_name(attrs: &[Attribute]) -> syn::Result<Option<String>> {
+    let mut name = None;
+
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("tool")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse::<LitStr>()?.value());
+
+                Ok(())
+            } else {
+                Err(meta.error("expected `name`"))
+            }
+        })?;
+    }
+
+    Ok(name)
+}
+
+/// Returns the name of a field or variant once deserialized,
+/// renamed with `rename_field` or `rename_variant` if the container has `#[serde(rename_all = "...")]`.
+fn property_name(
+    ident: &str,
+    attributes: &SerdeAttributes,
+    container: &SerdeAttributes,
+    rename: fn(&str, &str) -> Option<String>,
+) -> syn::Result<String> {
+    if let Some(rename) = &attributes.rename {
+        return Ok(rename.clone());
+    }
+
+    let ident = ident.strip_prefix("r#").unwrap_or(ident);
+
+    match &container.rename_all {
+        Some(rule) => {
+            rename(ident, &rule.value()).ok_or_else(|| syn::Error::new(rule.span(), "unknown case"))
+        }
+        None => Ok(ident.to_string()),
+    }
+}
+
+/// Converts the identifier of a field, in snake case, to the case named `rule`, like serde's `rename_all`.
+fn rename_field(field: &str, rule: &str) -> Option<String> {
+    Some(match rule {
+        "lowercase" | "snake_case" => field.to_string(),
+        "UPPERCASE" | "SCREAMING_SNAKE_CASE" => field.to_ascii_uppercase(),
+        "PascalCase" | "camelCase" => {
+            let mut pascal = String::new();
+            let mut capitalize = true;
+
+            for c in field.chars() {
+                if c == '_' {
+                    capitalize = true;
+                } else if capitalize {
+                    pascal.push(c.to_ascii_uppercase());
+                    capitalize = false;
+                } else {
+                    pascal.push(c);
+                }
+            }
+
+            if rule == "camelCase" {
+                lowercase_first(&pascal)
+            } else {
+                pascal
+            }
+        }
+        "kebab-case" => field.replace('_', "-"),
+        "SCREAMING-KEBAB-CASE" => field.to_ascii_uppercase().replace('_', "-"),
+        _ => return None,
+    })
+}
+
+/// Converts the identifier of a variant or type, in Pascal case, to the case named `rule`, like serde's `rename_all`.
+fn rename_variant(variant: &str, rule: &str) -> Option<String> {
+    let snake = || {
+        let mut snake = String::new();
+
+        for (i, c) in variant.char_indices() {
+            if i > 0 && c.is_uppercase() {
+                snake.push('_');
+            }
+
+            snake.push(c.to_ascii_lowercase());
+        }
+
+        snake
+    };
+
+    Some(match rule {
+        "PascalCase" => variant.to_string(),
+        "lowercase" => variant.to_ascii_lowercase(),
+        "UPPERCASE" => variant.to_ascii_uppercase(),
+        "camelCase" => lowercase_first(variant),
+        "snake_case" => snake(),
+        "SCREAMING_SNAKE_CASE" => snake().to_ascii_uppercase(),
+        "kebab-case" => snake().replace('_', "-"),
+        "SCREAMING-KEBAB-CASE" => snake().to_ascii_uppercase().replace('_', "-"),
+        _ => return None,
+    })
+}
+
+fn lowercase_first(ident: &str) -> String {
+    let mut chars = ident.chars();
+
+    chars
+        .next()
+        .map(|first| first.to_ascii_lowercase().to_string() + chars.as_str())
+        .unwrap_or_default()
+}
+
+/// Returns the lines of the doc comment in `attrs`, trimmed and joined.
+fn doc_comment(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(meta) if meta.path.is_ident("doc") => match &meta.value {
+                Expr::Lit(expr) => match &expr.lit {
+                    Lit::Str(line) => Some(line.value().trim().to_string()),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    let doc = lines.join("\n").trim().to_string();
+
+    (!doc.is_empty()).then_some(doc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cases() {
+        assert_eq!(
+            rename_variant("GetWeather", "snake_case").unwrap(),
+            "get_weather"
+        );
+        assert_eq!(
+            rename_variant("DegreesCelsius", "lowercase").unwrap(),
+            "degreescelsius"
+        );
+        assert_eq!(
+            rename_variant("DegreesCelsius", "SCREAMING-KEBAB-CASE").unwrap(),
+            "DEGREES-CELSIUS"
+        );
+        assert_eq!(rename_variant("Celsius", "camelCase").unwrap(), "celsius");
+        assert_eq!(rename_field("city_name", "camelCase").unwrap(), "cityName");
+        assert_eq!(rename_field("city_name", "PascalCase").unwrap(), "CityName");
+        assert_eq!(rename_field("city_name", "lowercase").unwrap(), "city_name");
+        assert_eq!(rename_field("city_name", "UPPERCASE").unwrap(), "CITY_NAME");
+        assert_eq!(rename_field("city", "Title Case"), None);
+    }
+
+    #[test]
+    fn flatten() {
+        let input: DeriveInput = syn::parse_quote! {
+            struct GetWeather {
+                city: String,
+                #[serde(flatten)]
+                options: Options,
+            }
+        };
+        let error = expand_openai_tool(&input).unwrap_err();
+
+        assert!(error.to_string().contains("flatten"));
+    }
+
+    #[test]
+    fn serialize_only_renames() {
+        let input: DeriveInput = syn::parse_quote! {
+            struct GetWeather {
+                #[serde(rename(serialize = "cityName"))]
+                city_name: String,
+            }
+        };
+        let error = expand_openai_tool(&input).unwrap_err();
+
+        assert!(error.to_string().contains("deserialize"));
+    }
+}
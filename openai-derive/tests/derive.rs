@@ -0,0 +1,137 @@
+use openai::chat::{OpenAiTool, ToolParameter};
+use serde::Deserialize;
+use serde_json::json;
+
+/// Gets the current weather of a city.
+///
+/// Temperatures are rounded to the nearest degree.
+#[derive(Deserialize, OpenAiTool)]
+#[serde(rename_all = "camelCase")]
+struct GetWeather {
+    /// The name of the city, such as "Paris".
+    city_name: String,
+    unit: Option<Unit>,
+    #[serde(default, rename = "days")]
+    forecast_days: u8,
+    #[serde(skip)]
+    #[allow(dead_code)]
+    cache: Vec<String>,
+}
+
+#[derive(Deserialize, ToolParameter, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Unit {
+    Celsius,
+    Fahrenheit,
+}
+
+#[derive(Deserialize, OpenAiTool)]
+#[tool(name = "now")]
+struct CurrentTime {}
+
+#[derive(Deserialize, OpenAiTool)]
+struct Lookup<T> {
+    #[serde(rename(deserialize = "q"))]
+    query: T,
+    #[serde(rename(serialize = "max", deserialize = "limit"))]
+    max_results: Option<u8>,
+}
+
+#[test]
+fn tools() {
+    let tool = GetWeather::tool();
+
+    assert_eq!(tool.function.name, "get_weather");
+    assert_eq!(
+        tool.function.description.as_deref(),
+        Some("Gets the current weather of a city.\n\nTemperatures are rounded to the nearest degree.")
+    );
+    assert_eq!(
+        tool.function.parameters,
+        json!({
+            "type": "object",
+            "properties": {
+                "cityName": {"type": "string", "description": "The name of the city, such as \"Paris\"."},
+                "unit": {"type": "string", "enum": ["celsius", "fahrenheit"]},
+                "days": {"type": "integer", "minimum": 0},
+            },
+            "required": ["cityName"],
+            "additionalProperties": false,
+        })
+    );
+
+    let tool = CurrentTime::tool();
+
+    assert_eq!(tool.function.name, "now");
+    assert_eq!(tool.function.description, None);
+    assert_eq!(tool.function.parameters["properties"], json!({}));
+}
+
+#[test]
+fn arguments() {
+    let arguments = GetWeather::from_arguments(r#"{"cityName":"Paris","unit":"celsius"}"#).unwrap();
+
+    assert_eq!(arguments.city_name, "Paris");
+    assert_eq!(arguments.unit, Some(Unit::Celsius));
+    assert_eq!(arguments.forecast_days, 0);
+    assert!(GetWeather::from_arguments(r#"{"city":"Paris"}"#).is_err());
+    assert!(CurrentTime::from_arguments("{}").is_ok());
+    assert_eq!(
+        Unit::schema(),
+        json!({"type": "string", "enum": ["celsius", "fahrenheit"]})
+    );
+}
+
+#[test]
+fn generics() {
+    let tool = Lookup::<String>::tool();
+
+    assert_eq!(
+        tool.function.parameters["properties"],
+        json!({
+            "q": {"type": "string"},
+            "limit": {"type": "integer", "minimum": 0},
+        })
+    );
+    assert_eq!(tool.function.parameters["required"], json!(["q"]));
+
+    let arguments = Lookup::<u32>::from_arguments(r#"{"q":7,"limit":3}"#).unwrap();
+
+    assert_eq!(arguments.query, 7);
+    assert_eq!(arguments.max_results, Some(3));
+}
+
+macro_rules! renamed {
+    ($($ident:ident: $rule:literal => $name:literal),* $(,)?) => {$({
+        #[derive(Deserialize, OpenAiTool)]
+        #[serde(rename_all = $rule)]
+        struct $ident {
+            city_name: String,
+        }
+
+        assert_eq!(
+            $ident::tool().function.parameters["required"],
+            json!([$name]),
+            "{}",
+            $rule
+        );
+
+        let arguments = json!({$name: "Paris"}).to_string();
+
+        assert_eq!($ident::from_arguments(&arguments).unwrap().city_name, "Paris");
+    })*};
+}
+
+#[test]
+fn renamed_fields() {
+    renamed! {
+        Lowercase: "lowercase" => "city_name",
+        Uppercase: "UPPERCASE" => "CITY_NAME",
+        Pascal: "PascalCase" => "CityName",
+        Camel: "camelCase" => "cityName",
+        Snake: "snake_case" => "city_name",
+        ScreamingSnake: "SCREAMING_SNAKE_CASE" => "CITY_NAME",
+        Kebab: "kebab-case" => "city-name",
+        ScreamingKebab: "SCREAMING-KEBAB-CASE" => "CITY-NAME",
+    }
+}
@@ -1,9 +1,22 @@
 //! Given a chat conversation, the model will return a chat completion response.
 
-use super::{openai_post, ApiResponseOrError, Usage};
+use super::{
+    null_as_default, openai_post, openai_post_stream, perplexity, request_client,
+    validate::{check_context, check_logit_bias, check_range, check_stop},
+    Error, EventStream, LogitBias, OpenAiClient, Response, Usage, Validate, ValidationError,
+};
 use derive_builder::Builder;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize, Serializer};
+use serde_json::Value;
+
+pub mod agent;
+mod conversation;
+mod tool;
+
+pub use conversation::{Conversation, Truncation};
+#[cfg(feature = "derive")]
+pub use openai_derive::{OpenAiTool, ToolParameter};
+pub use tool::{OpenAiTool, ToolParameter};
 
 #[derive(Deserialize, Clone)]
 pub struct ChatCompletion {
@@ -20,17 +33,293 @@ pub struct ChatCompletionChoice {
     pub index: u64,
     pub message: ChatCompletionMessage,
     pub finish_reason: String,
+    /// Only sent if requested with `logprobs`.
+    pub logprobs: Option<ChatCompletionLogprobs>,
+}
+
+/// The log probabilities of the tokens of a [`ChatCompletionChoice`], requested with `logprobs`.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct ChatCompletionLogprobs {
+    /// The tokens of the content of the message.
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub content: Vec<TokenLogprob>,
+    /// The tokens of the refusal of the message.
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub refusal: Vec<TokenLogprob>,
+}
+
+/// A token generated by the model and its log probability.
+#[derive(Deserialize, Clone, Debug)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    /// The UTF-8 bytes of the token, which may be only part of a character.
+    pub bytes: Option<Vec<u8>>,
+    /// The `top_logprobs` most likely tokens at this position, from most to least likely.
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+/// One of the most likely tokens at a position, see [`TokenLogprob::top_logprobs`].
+#[derive(Deserialize, Clone, Debug)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+    pub bytes: Option<Vec<u8>>,
+}
+
+impl ChatCompletionLogprobs {
+    /// Returns the perplexity of the content, which is the exponential of the average negative log probability of its tokens.
+    /// It is 1 when the model was sure of every token and grows as it was less sure, or is `None` without any tokens.
+    pub fn perplexity(&self) -> Option<f64> {
+        perplexity(self.content.iter().map(|token| token.logprob))
+    }
+
+    /// Returns the most likely tokens at `position` of the content with their log probabilities, from most to least likely.
+    /// This is empty if the position is out of bounds or `top_logprobs` wasn't requested.
+    pub fn top_alternatives(&self, position: usize) -> Vec<(&str, f64)> {
+        self.content
+            .get(position)
+            .into_iter()
+            .flat_map(|token| &token.top_logprobs)
+            .map(|top| (top.token.as_str(), top.logprob))
+            .collect()
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ChatCompletionMessage {
     /// The role of the author of this message.
     pub role: ChatCompletionMessageRole,
-    /// The contents of the message
-    pub content: String,
-    /// The name of the user in a multi-user chat
+    /// The contents of the message.
+    /// Only missing from assistant messages that call tools instead of answering.
+    pub content: Option<String>,
+    /// The name of the user in a multi-user chat,
+    /// or of the function whose result this is for messages with the [`Function`](ChatCompletionMessageRole::Function) role.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The tools called by the assistant, see [`ChatCompletionBuilder::tools`].
+    #[serde(
+        default,
+        deserialize_with = "null_as_default",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub tool_calls: Vec<ToolCall>,
+    /// The call that this message holds the result of, for messages with the [`Tool`](ChatCompletionMessageRole::Tool) role.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// The function called by the assistant, see [`ChatCompletionBuilder::functions`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<FunctionCall>,
+    /// Why the assistant refused to answer, instead of content in the [format](ChatCompletionBuilder::response_format) requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<String>,
+}
+
+impl ChatCompletionMessage {
+    pub fn new(role: ChatCompletionMessageRole, content: impl Into<String>) -> Self {
+        ChatCompletionMessage {
+            role,
+            content: Some(content.into()),
+            name: None,
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+            function_call: None,
+            refusal: None,
+        }
+    }
+
+    /// Creates a message holding the result of the tool call with the ID `tool_call_id`.
+    pub fn tool(tool_call_id: impl Into<String>, content: impl Into<String>) -> Self {
+        ChatCompletionMessage {
+            tool_call_id: Some(tool_call_id.into()),
+            ..ChatCompletionMessage::new(ChatCompletionMessageRole::Tool, content)
+        }
+    }
+}
+
+/// A tool the model may call, see [`ChatCompletionBuilder::tools`].
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Tool {
+    #[serde(rename = "type")]
+    pub tool_type: ToolType,
+    pub function: Function,
+}
+
+impl Tool {
+    /// Creates a function tool. `parameters` is the JSON Schema of the object the function takes.
+    pub fn function(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        parameters: Value,
+    ) -> Self {
+        Tool {
+            tool_type: ToolType::Function,
+            function: Function {
+                name: name.into(),
+                description: Some(description.into()),
+                parameters,
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolType {
+    #[default]
+    Function,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Function {
+    /// The name of the function, made of `a-z`, `A-Z`, `0-9`, `_` and `-`, up to 64 characters.
+    pub name: String,
+    /// What the function does, used by the model to choose when and how to call it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The JSON Schema of the object the function takes.
+    pub parameters: Value,
+}
+
+/// A call of a [`Tool`] by the model.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub tool_type: ToolType,
+    pub function: FunctionCall,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FunctionCall {
+    pub name: String,
+    /// The arguments to call the function with, as JSON generated by the model.
+    /// They may not be valid JSON or match the parameters of the function.
+    pub arguments: String,
+}
+
+/// The format of the messages generated by the model, see [`ChatCompletionBuilder::response_format`].
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    /// Makes the model generate a JSON object.
+    /// The messages of the chat must also ask for JSON, or the model may generate whitespace until it runs out of tokens.
+    JsonObject,
+    /// Makes the model generate JSON matching a schema.
+    JsonSchema {
+        json_schema: JsonSchema,
+    },
+}
+
+impl ResponseFormat {
+    /// Makes the model generate JSON matching `schema`, which it is guaranteed to follow if `strict` is `true`.
+    /// Strict schemas must require every property and not allow additional ones.
+    pub fn json_schema(name: impl Into<String>, schema: Value, strict: bool) -> Self {
+        ResponseFormat::JsonSchema {
+            json_schema: JsonSchema {
+                name: name.into(),
+                description: None,
+                schema,
+                strict: Some(strict),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct JsonSchema {
+    /// The name of the format, made of `a-z`, `A-Z`, `0-9`, `_` and `-`, up to 64 characters.
+    pub name: String,
+    /// What the format is for, used by the model to choose how to answer.
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub schema: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+/// Controls which tool is called by the model, see [`ChatCompletionBuilder::tool_choice`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolChoice {
+    /// The model does not call any tool and answers with a message instead.
+    None,
+    /// The model picks between answering and calling one or more tools. This is the default when there are tools.
+    Auto,
+    /// The model calls one or more tools.
+    Required,
+    /// The model calls the function with this name.
+    Function(String),
+}
+
+impl Serialize for ToolChoice {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            ToolChoice::None => serializer.serialize_str("none"),
+            ToolChoice::Auto => serializer.serialize_str("auto"),
+            ToolChoice::Required => serializer.serialize_str("required"),
+            ToolChoice::Function(name) => serde_json::json!({
+                "type": "function",
+                "function": { "name": name },
+            })
+            .serialize(serializer),
+        }
+    }
+}
+
+/// A chunk of a streamed chat completion, see [`ChatCompletionBuilder::create_stream`].
+#[derive(Deserialize, Clone, Debug)]
+pub struct ChatCompletionDelta {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChatCompletionChoiceDelta>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ChatCompletionChoiceDelta {
+    pub index: u64,
+    pub delta: ChatCompletionMessageDelta,
+    /// Only set on the last chunk of a choice.
+    pub finish_reason: Option<String>,
+    /// The log probabilities of the tokens of this chunk, if requested with `logprobs`.
+    pub logprobs: Option<ChatCompletionLogprobs>,
+}
+
+/// The part of a [`ChatCompletionMessage`] generated since the previous chunk.
+#[derive(Deserialize, Clone, Debug)]
+pub struct ChatCompletionMessageDelta {
+    /// Only sent in the first chunk of a choice.
+    pub role: Option<ChatCompletionMessageRole>,
+    pub content: Option<String>,
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub tool_calls: Vec<ToolCallDelta>,
+    pub refusal: Option<String>,
+}
+
+/// The part of a [`ToolCall`] generated since the previous chunk.
+#[derive(Deserialize, Clone, Debug)]
+pub struct ToolCallDelta {
+    /// The position of the call among the calls of the message, the same in every chunk of a call.
+    pub index: u32,
+    /// Only sent in the first chunk of a call.
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub tool_type: Option<ToolType>,
+    pub function: Option<FunctionCallDelta>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct FunctionCallDelta {
+    /// Only sent in the first chunk of a call.
     pub name: Option<String>,
+    pub arguments: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Copy)]
@@ -39,12 +328,17 @@ pub enum ChatCompletionMessageRole {
     System,
     User,
     Assistant,
+    /// The result of a tool call, see [`ChatCompletionMessage::tool`].
+    Tool,
+    /// The result of a function call, with the name of the function as the name of the message.
+    Function,
 }
 
 #[derive(Serialize, Builder, Debug, Clone)]
 #[builder(pattern = "owned")]
 #[builder(name = "ChatCompletionBuilder")]
 #[builder(setter(strip_option, into))]
+#[builder(build_fn(error = "Error"))]
 pub struct ChatCompletionRequest {
     /// ID of the model to use. Currently, only `gpt-3.5-turbo` and `gpt-3.5-turbo-0301` are supported.
     model: String,
@@ -68,7 +362,7 @@ pub struct ChatCompletionRequest {
     n: Option<u8>,
     /// If set, partial message deltas will be sent, like in ChatGPT. Tokens will be sent as data-only [server-sent events](https://developer.mozilla.org/en-US/docs/Web/API/Server-sent_events/Using_server-sent_events#Event_stream_format)
     /// as they become available, with the stream terminated by a `data: [DONE]` message.
-    #[builder(setter(skip), default)] // skipped until properly implemented
+    #[builder(setter(skip), default)] // set by `ChatCompletionBuilder::create_stream`
     #[serde(skip_serializing_if = "Option::is_none")]
     stream: Option<bool>,
     /// Up to 4 sequences where the API will stop generating further tokens.
@@ -76,6 +370,8 @@ pub struct ChatCompletionRequest {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     stop: Vec<String>,
     /// The maximum number of tokens allowed for the generated answer. By default, the number of tokens the model can return will be (4096 - prompt tokens).
+    /// With the `tokenizer` feature, prompt tokens can be counted with `tokenizer::count_chat_tokens`,
+    /// which doesn't support `gpt-4o` and later models.
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     max_tokens: Option<u64>,
@@ -93,14 +389,60 @@ pub struct ChatCompletionRequest {
     frequency_penalty: Option<f32>,
     /// Modify the likelihood of specified tokens appearing in the completion.
     ///
-    /// Accepts a json object that maps tokens (specified by their token ID in the tokenizer) to an associated bias value from -100 to 100. Mathematically, the bias is added to the logits generated by the model prior to sampling. The exact effect will vary per model, but values between -1 and 1 should decrease or increase likelihood of selection; values like -100 or 100 should result in a ban or exclusive selection of the relevant token.
+    /// Maps tokens (specified by their token ID in the tokenizer) to an associated bias value from -100 to 100. Mathematically, the bias is added to the logits generated by the model prior to sampling. The exact effect will vary per model, but values between -1 and 1 should decrease or increase likelihood of selection; values like -100 or 100 should result in a ban or exclusive selection of the relevant token.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "LogitBias::is_empty")]
+    logit_bias: LogitBias,
+    /// Whether to return the log probabilities of the tokens of the message, in [`ChatCompletionChoice::logprobs`].
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logprobs: Option<bool>,
+    /// The number of most likely tokens to return at each position, from 0 to 20, with their log probabilities.
+    /// Requires `logprobs` to be `true`.
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    logit_bias: Option<HashMap<String, f32>>,
+    top_logprobs: Option<u8>,
     /// A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse. [Learn more](https://platform.openai.com/docs/guides/safety-best-practices/end-user-ids).
     #[builder(default)]
     #[serde(skip_serializing_if = "String::is_empty")]
     user: String,
+    /// The tools the model may call. Tool calls are returned in [`ChatCompletionMessage::tool_calls`]
+    /// and their results are sent back in messages created with [`ChatCompletionMessage::tool`].
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<Tool>,
+    /// Controls which of the `tools` is called by the model.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<ToolChoice>,
+    /// Whether the model may call several tools at once. Defaults to `true`.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parallel_tool_calls: Option<bool>,
+    /// Functions the model may call, which is deprecated in favor of `tools`.
+    /// Function calls are returned in [`ChatCompletionMessage::function_call`].
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    functions: Vec<Function>,
+    /// The format of the message generated by the model, such as JSON. Defaults to text.
+    ///
+    /// See [`ChatCompletionBuilder::create_parsed`] to read JSON messages.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<ResponseFormat>,
+    /// The client to make the request with.
+    /// Defaults to the client configured with [`set_key`](crate::set_key).
+    #[builder(default)]
+    #[serde(skip)]
+    client: Option<OpenAiClient>,
+    /// The organization the request counts towards, instead of that of its client.
+    #[builder(default)]
+    #[serde(skip)]
+    organization: Option<String>,
+    /// The project the request counts towards, instead of that of its client.
+    #[builder(default)]
+    #[serde(skip)]
+    project: Option<String>,
 }
 
 impl ChatCompletion {
@@ -113,46 +455,472 @@ impl ChatCompletion {
             .messages(messages)
     }
 
-    pub async fn create(request: &ChatCompletionRequest) -> ApiResponseOrError<Self> {
-        openai_post("chat/completions", request).await
+    pub async fn create(request: &ChatCompletionRequest) -> Result<Self, Error> {
+        Ok(Self::create_with_metadata(request).await?.body)
+    }
+
+    /// Like [`ChatCompletion::create`], but the metadata of the response is returned too.
+    pub async fn create_with_metadata(
+        request: &ChatCompletionRequest,
+    ) -> Result<Response<Self>, Error> {
+        request.validate()?;
+
+        let client = request_client(
+            request.client.as_ref(),
+            request.organization.as_ref(),
+            request.project.as_ref(),
+        );
+
+        openai_post(client.as_ref(), "chat/completions", request).await
+    }
+}
+
+impl Validate for ChatCompletionRequest {
+    fn validate(&self) -> Result<(), ValidationError> {
+        if self.messages.is_empty() {
+            return Err(ValidationError::Empty {
+                parameter: "messages",
+            });
+        }
+
+        check_range("temperature", self.temperature, 0.0, 2.0)?;
+        check_range("top_p", self.top_p, 0.0, 1.0)?;
+        check_range("presence_penalty", self.presence_penalty, -2.0, 2.0)?;
+        check_range("frequency_penalty", self.frequency_penalty, -2.0, 2.0)?;
+        check_range("top_logprobs", self.top_logprobs, 0.0, 20.0)?;
+        check_stop(&self.stop)?;
+        check_logit_bias(&self.logit_bias)?;
+
+        if let Some(max_tokens) = self.max_tokens {
+            // Prompts of models with an unsupported encoding, like `gpt-4o`, aren't counted,
+            // so only `max_tokens` is checked against the context window.
+            #[cfg(feature = "tokenizer")]
+            let prompt_tokens =
+                crate::tokenizer::count_chat_tokens(&self.model, &self.messages).unwrap_or(0);
+            #[cfg(not(feature = "tokenizer"))]
+            let prompt_tokens = 0;
+
+            check_context(
+                &self.model,
+                prompt_tokens as u32,
+                max_tokens.try_into().unwrap_or(u32::MAX),
+            )?;
+        }
+
+        Ok(())
     }
 }
 
 impl ChatCompletionBuilder {
-    pub async fn create(self) -> ApiResponseOrError<ChatCompletion> {
-        ChatCompletion::create(&self.build().unwrap()).await
+    pub async fn create(self) -> Result<ChatCompletion, Error> {
+        ChatCompletion::create(&self.build()?).await
+    }
+
+    /// Like [`ChatCompletionBuilder::create`], but the metadata of the response is returned too.
+    pub async fn create_with_metadata(self) -> Result<Response<ChatCompletion>, Error> {
+        ChatCompletion::create_with_metadata(&self.build()?).await
+    }
+
+    /// Like [`ChatCompletionBuilder::create`], but the message generated by the model is deserialized as a `T`,
+    /// usually with a JSON [`response_format`](ChatCompletionBuilder::response_format).
+    ///
+    /// Fails with [`Error::Refusal`] if the model refuses to answer,
+    /// or with [`Error::InvalidOutput`] if the message is not a valid `T`.
+    pub async fn create_parsed<T>(self) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let chat_completion = self.create().await?;
+        let Some(choice) = chat_completion.choices.into_iter().next() else {
+            return Err(Error::IncompleteResponse(
+                "the chat completion has no choices".to_string(),
+            ));
+        };
+
+        if let Some(refusal) = choice.message.refusal {
+            return Err(Error::Refusal(refusal));
+        }
+
+        let content = choice.message.content.unwrap_or_default();
+
+        serde_json::from_str(&content).map_err(|source| Error::InvalidOutput { content, source })
+    }
+
+    /// Like [`ChatCompletionBuilder::create`],
+    /// but the message is streamed back in chunks as it is generated.
+    pub async fn create_stream(self) -> Result<EventStream<ChatCompletionDelta>, Error> {
+        Ok(self.create_stream_with_metadata().await?.body)
+    }
+
+    /// Like [`ChatCompletionBuilder::create_stream`], but the metadata of the response is returned too.
+    pub async fn create_stream_with_metadata(
+        self,
+    ) -> Result<Response<EventStream<ChatCompletionDelta>>, Error> {
+        let mut request = self.build()?;
+
+        request.validate()?;
+        request.stream = Some(true);
+
+        let client = request_client(
+            request.client.as_ref(),
+            request.organization.as_ref(),
+            request.project.as_ref(),
+        );
+
+        openai_post_stream(client.as_ref(), "chat/completions", &request).await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::set_key;
+    use crate::{
+        set_key,
+        stub::{StubResponse, StubServer},
+    };
     use dotenvy::dotenv;
+    use futures_util::StreamExt;
+    use serde_json::json;
     use std::env;
 
+    fn hello() -> ChatCompletionMessage {
+        ChatCompletionMessage::new(ChatCompletionMessageRole::User, "Hello!")
+    }
+
     #[tokio::test]
     async fn chat() {
         dotenv().ok();
         set_key(env::var("OPENAI_KEY").unwrap());
 
-        let chat_completion = ChatCompletion::builder(
-            "gpt-3.5-turbo",
-            [ChatCompletionMessage {
-                role: ChatCompletionMessageRole::User,
-                content: "Hello!".to_string(),
-                name: None,
-            }],
-        )
-        .temperature(0.0)
-        .create()
-        .await
-        .unwrap()
-        .unwrap();
+        let chat_completion = ChatCompletion::builder("gpt-3.5-turbo", [hello()])
+            .temperature(0.0)
+            .create()
+            .await
+            .unwrap();
 
         assert_eq!(
             chat_completion.choices.first().unwrap().message.content,
-            "Hello there! How can I assist you today?"
+            Some("Hello there! How can I assist you today?".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn chat_stub() {
+        let server = StubServer::start(vec![StubResponse::json(
+            200,
+            json!({
+                "id": "chatcmpl-123",
+                "object": "chat.completion",
+                "created": 1677652288,
+                "model": "gpt-3.5-turbo-0301",
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": "Hello there!"},
+                    "finish_reason": "stop",
+                }],
+                "usage": {"prompt_tokens": 9, "completion_tokens": 3, "total_tokens": 12},
+            }),
+        )])
+        .await;
+        let client = OpenAiClient::new("key").base_url(server.url());
+        let chat_completion = ChatCompletion::builder("gpt-3.5-turbo", [hello()])
+            .client(&client)
+            .create()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            chat_completion
+                .choices
+                .first()
+                .unwrap()
+                .message
+                .content
+                .as_deref(),
+            Some("Hello there!")
+        );
+
+        let request = &server.requests()[0];
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/v1/chat/completions");
+        assert_eq!(request.json()["messages"][0]["content"], "Hello!");
+        assert_eq!(request.json().get("stream"), None);
+    }
+
+    #[tokio::test]
+    async fn chat_logprobs() {
+        let server = StubServer::start(vec![StubResponse::json(
+            200,
+            json!({
+                "id": "chatcmpl-123",
+                "object": "chat.completion",
+                "created": 1677652288,
+                "model": "gpt-4o-mini",
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": "Hi!"},
+                    "finish_reason": "stop",
+                    "logprobs": {
+                        "content": [
+                            {
+                                "token": "Hi",
+                                "logprob": -0.5,
+                                "bytes": [72, 105],
+                                "top_logprobs": [
+                                    {"token": "Hi", "logprob": -0.5, "bytes": [72, 105]},
+                                    {"token": "Hello", "logprob": -1.0, "bytes": [72, 101, 108, 108, 111]},
+                                ],
+                            },
+                            {"token": "!", "logprob": -1.5, "bytes": [33], "top_logprobs": []},
+                        ],
+                        "refusal": null,
+                    },
+                }],
+            }),
+        )])
+        .await;
+        let client = OpenAiClient::new("key").base_url(server.url());
+        let chat_completion = ChatCompletion::builder("gpt-4o-mini", [hello()])
+            .logprobs(true)
+            .top_logprobs(2)
+            .client(&client)
+            .create()
+            .await
+            .unwrap();
+        let logprobs = chat_completion.choices[0].logprobs.as_ref().unwrap();
+
+        assert_eq!(logprobs.perplexity(), Some(1f64.exp()));
+        assert_eq!(
+            logprobs.top_alternatives(0),
+            [("Hi", -0.5), ("Hello", -1.0)]
+        );
+        assert!(logprobs.top_alternatives(2).is_empty());
+        assert!(logprobs.refusal.is_empty());
+
+        let request = server.requests()[0].json();
+
+        assert_eq!(request["logprobs"], true);
+        assert_eq!(request["top_logprobs"], 2);
+    }
+
+    #[tokio::test]
+    async fn chat_tools() {
+        let server = StubServer::start(vec![StubResponse::json(
+            200,
+            json!({
+                "id": "chatcmpl-123",
+                "object": "chat.completion",
+                "created": 1677652288,
+                "model": "gpt-3.5-turbo-0613",
+                "choices": [{
+                    "index": 0,
+                    "message": {
+                        "role": "assistant",
+                        "content": null,
+                        "tool_calls": [{
+                            "id": "call_abc",
+                            "type": "function",
+                            "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"},
+                        }],
+                    },
+                    "finish_reason": "tool_calls",
+                }],
+            }),
+        )])
+        .await;
+        let client = OpenAiClient::new("key").base_url(server.url());
+        let weather = Tool::function(
+            "get_weather",
+            "Gets the current weather of a city",
+            json!({
+                "type": "object",
+                "properties": {"city": {"type": "string"}},
+                "required": ["city"],
+            }),
+        );
+        let chat_completion = ChatCompletion::builder("gpt-3.5-turbo", [hello()])
+            .client(&client)
+            .tools([weather])
+            .tool_choice(ToolChoice::Function("get_weather".to_string()))
+            .create()
+            .await
+            .unwrap();
+        let choice = &chat_completion.choices[0];
+
+        assert_eq!(choice.finish_reason, "tool_calls");
+        assert_eq!(choice.message.content, None);
+        assert_eq!(choice.message.tool_calls[0].id, "call_abc");
+        assert_eq!(choice.message.tool_calls[0].function.name, "get_weather");
+        assert_eq!(
+            choice.message.tool_calls[0].function.arguments,
+            r#"{"city":"Paris"}"#
+        );
+
+        let request = server.requests()[0].json();
+
+        assert_eq!(request["tools"][0]["type"], "function");
+        assert_eq!(request["tools"][0]["function"]["name"], "get_weather");
+        assert_eq!(
+            request["tool_choice"],
+            json!({"type": "function", "function": {"name": "get_weather"}})
+        );
+        assert_eq!(request.get("functions"), None);
+
+        let messages = [
+            choice.message.clone(),
+            ChatCompletionMessage::tool("call_abc", "Sunny"),
+        ];
+
+        assert_eq!(
+            serde_json::to_value(messages).unwrap(),
+            json!([
+                {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_abc",
+                        "type": "function",
+                        "function": {"name": "get_weather", "arguments": r#"{"city":"Paris"}"#},
+                    }],
+                },
+                {"role": "tool", "content": "Sunny", "tool_call_id": "call_abc"},
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn chat_parsed() {
+        let answer = |message: serde_json::Value| {
+            StubResponse::json(
+                200,
+                json!({
+                    "id": "chatcmpl-123",
+                    "object": "chat.completion",
+                    "created": 1677652288,
+                    "model": "gpt-4o-2024-08-06",
+                    "choices": [{"index": 0, "message": message, "finish_reason": "stop"}],
+                }),
+            )
+        };
+        let server = StubServer::start(vec![
+            answer(json!({"role": "assistant", "content": r#"{"city":"Paris","temperature":21}"#})),
+            answer(
+                json!({"role": "assistant", "content": null, "refusal": "I can't help with that."}),
+            ),
+            answer(json!({"role": "assistant", "content": "It is 21 degrees in Paris."})),
+        ])
+        .await;
+        let client = OpenAiClient::new("key").base_url(server.url());
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Weather {
+            city: String,
+            temperature: i32,
+        }
+
+        let format = ResponseFormat::json_schema(
+            "weather",
+            json!({
+                "type": "object",
+                "properties": {"city": {"type": "string"}, "temperature": {"type": "integer"}},
+                "required": ["city", "temperature"],
+                "additionalProperties": false,
+            }),
+            true,
+        );
+        let builder = || {
+            ChatCompletion::builder("gpt-4o", [hello()])
+                .client(&client)
+                .response_format(format.clone())
+        };
+        let weather: Weather = builder().create_parsed().await.unwrap();
+
+        assert_eq!(
+            weather,
+            Weather {
+                city: "Paris".to_string(),
+                temperature: 21,
+            }
+        );
+
+        let request = server.requests()[0].json();
+
+        assert_eq!(request["response_format"]["type"], "json_schema");
+        assert_eq!(request["response_format"]["json_schema"]["name"], "weather");
+        assert_eq!(request["response_format"]["json_schema"]["strict"], true);
+
+        match builder().create_parsed::<Weather>().await {
+            Err(Error::Refusal(refusal)) => assert_eq!(refusal, "I can't help with that."),
+            _ => panic!("expected a refusal"),
+        }
+
+        match builder().create_parsed::<Weather>().await {
+            Err(Error::InvalidOutput { content, .. }) => {
+                assert_eq!(content, "It is 21 degrees in Paris.")
+            }
+            _ => panic!("expected invalid output"),
+        }
+
+        assert_eq!(
+            serde_json::to_value(ResponseFormat::JsonObject).unwrap(),
+            json!({"type": "json_object"})
         );
     }
+
+    #[tokio::test]
+    async fn chat_stream() {
+        let server = StubServer::start(vec![StubResponse::event_stream(&[
+            r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"gpt-3.5-turbo","choices":[{"index":0,"delta":{"role":"assistant"},"finish_reason":null}]}"#,
+            r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"gpt-3.5-turbo","choices":[{"index":0,"delta":{"content":"Hello"},"finish_reason":null}]}"#,
+            r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"gpt-3.5-turbo","choices":[{"index":0,"delta":{"content":" there!"},"finish_reason":null}]}"#,
+            r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"gpt-3.5-turbo","choices":[{"index":0,"delta":{},"finish_reason":"stop"}]}"#,
+            "[DONE]",
+            r#"{"id":"chatcmpl-2","object":"chat.completion.chunk","created":1,"model":"gpt-3.5-turbo","choices":[]}"#,
+        ])])
+        .await;
+        let client = OpenAiClient::new("key").base_url(server.url());
+        let deltas: Vec<ChatCompletionDelta> = ChatCompletion::builder("gpt-3.5-turbo", [hello()])
+            .client(&client)
+            .create_stream()
+            .await
+            .unwrap()
+            .map(|delta| delta.unwrap())
+            .collect()
+            .await;
+        let content: String = deltas
+            .iter()
+            .filter_map(|delta| delta.choices[0].delta.content.clone())
+            .collect();
+
+        assert_eq!(deltas.len(), 4);
+        assert_eq!(content, "Hello there!");
+        assert_eq!(deltas[3].choices[0].finish_reason.as_deref(), Some("stop"));
+        assert_eq!(server.requests()[0].json()["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn chat_stream_error() {
+        let server = StubServer::start(vec![StubResponse::event_stream(&[
+            r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"gpt-3.5-turbo","choices":[{"index":0,"delta":{"content":"Hello"},"finish_reason":null}]}"#,
+            r#"{"error":{"message":"The server had an error while processing your request.","type":"server_error","param":null,"code":null}}"#,
+            "[DONE]",
+        ])])
+        .await;
+        let client = OpenAiClient::new("key").base_url(server.url());
+        let mut stream = ChatCompletion::builder("gpt-3.5-turbo", [hello()])
+            .client(&client)
+            .create_stream()
+            .await
+            .unwrap();
+
+        assert!(stream.next().await.unwrap().is_ok());
+
+        match stream.next().await.unwrap() {
+            Err(Error::Api { error, .. }) => assert_eq!(error.error_type, "server_error"),
+            _ => panic!("expected an API error"),
+        }
+
+        assert!(stream.next().await.is_none());
+    }
 }
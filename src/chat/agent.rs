@@ -0,0 +1,589 @@
+//! Runs the tools called by the model until it answers, see [`Agent`].
+
+use super::{
+    ChatCompletion, ChatCompletionBuilder, ChatCompletionMessage, ChatCompletionMessageRole,
+    OpenAiTool, Tool, ToolCall, ToolType,
+};
+use crate::{Error, Usage};
+use futures_util::future::{self, BoxFuture, FutureExt};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+type Handler = Arc<dyn Fn(String) -> BoxFuture<'static, Result<String, String>> + Send + Sync>;
+type EventCallback = Arc<dyn Fn(&AgentEvent) + Send + Sync>;
+
+/// Answers a chat by calling the model, running the tools it calls with registered handlers,
+/// and sending their results back, until the model answers with a message that calls no tools.
+///
+/// A handler that fails, or a call of a tool without a handler, is reported to the model as the result of the call,
+/// so it can try again or answer without it.
+///
+/// If the builder has [`ChatCompletionBuilder::functions`], the tools of the agent are sent as functions instead,
+/// since the API rejects requests with both, and the functions are run by the handlers of the same name.
+/// Their calls are reported as [`ToolCall`]s with an empty `id`,
+/// and their results are sent back in messages with the [`Function`](super::ChatCompletionMessageRole::Function) role.
+///
+/// ## Examples
+///
+/// ```rust
+/// use openai::chat::{agent::Agent, ChatCompletion, ChatCompletionMessage, ChatCompletionMessageRole, Tool};
+/// use serde_json::json;
+///
+/// # async fn run() -> Result<(), openai::Error> {
+/// let agent = Agent::new()
+///     .max_iterations(5)
+///     .function(
+///         Tool::function("get_time", "Gets the current time", json!({"type": "object", "properties": {}})),
+///         |_arguments| async { Ok::<_, String>("12:00".to_string()) },
+///     );
+/// let question = ChatCompletionMessage::new(ChatCompletionMessageRole::User, "What time is it?");
+/// let run = agent.run(ChatCompletion::builder("gpt-3.5-turbo", [question])).await?;
+///
+/// println!("{:?}", run.message.content);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Agent {
+    tools: Vec<Tool>,
+    handlers: HashMap<String, Handler>,
+    max_iterations: u32,
+    max_tokens: Option<u32>,
+    parallel: bool,
+    on_event: Option<EventCallback>,
+}
+
+/// The answer of the model to a chat run by an [`Agent`].
+#[derive(Debug, Clone)]
+pub struct AgentRun {
+    /// The last message of the model, which calls no tools or functions.
+    pub message: ChatCompletionMessage,
+    /// The messages of the chat, followed by the messages of the model and the results of the tools it called.
+    pub messages: Vec<ChatCompletionMessage>,
+    /// How many chat completions were created.
+    pub iterations: u32,
+    /// The tokens used by every chat completion.
+    pub usage: Usage,
+}
+
+/// Something that happened while an [`Agent`] was running, see [`Agent::on_event`].
+#[derive(Debug)]
+pub enum AgentEvent<'a> {
+    /// The model responded to the chat.
+    Completion {
+        iteration: u32,
+        message: &'a ChatCompletionMessage,
+        usage: Option<Usage>,
+    },
+    /// A tool called by the model is about to run.
+    ToolCall { call: &'a ToolCall },
+    /// A tool called by the model finished running.
+    /// Errors are those of the handler or of the arguments generated by the model.
+    ToolResult {
+        call: &'a ToolCall,
+        result: &'a Result<String, String>,
+        duration: Duration,
+    },
+}
+
+impl Agent {
+    /// Creates an agent without tools, which gives up after 10 completions.
+    pub fn new() -> Self {
+        Agent {
+            tools: Vec::new(),
+            handlers: HashMap::new(),
+            max_iterations: 10,
+            max_tokens: None,
+            parallel: true,
+            on_event: None,
+        }
+    }
+
+    /// Registers `T` as a tool, whose calls are handled by `handler` with the arguments generated by the model.
+    pub fn tool<T, F, Fut, E>(self, handler: F) -> Self
+    where
+        T: OpenAiTool + Send + 'static,
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String, E>> + Send + 'static,
+        E: Display,
+    {
+        let handler = Arc::new(handler);
+
+        self.function(T::tool(), move |arguments| {
+            let handler = handler.clone();
+
+            async move {
+                let arguments = T::from_arguments(&arguments)
+                    .map_err(|error| format!("invalid arguments: {error}"))?;
+
+                handler(arguments).await.map_err(|error| error.to_string())
+            }
+        })
+    }
+
+    /// Registers `tool`, whose calls are handled by `handler` with the arguments generated by the model as JSON.
+    pub fn function<F, Fut, E>(mut self, tool: Tool, handler: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String, E>> + Send + 'static,
+        E: Display,
+    {
+        let handler: Handler = Arc::new(move |arguments| {
+            handler(arguments)
+                .map(|result| result.map_err(|error| error.to_string()))
+                .boxed()
+        });
+
+        self.tools
+            .retain(|existing| existing.function.name != tool.function.name);
+        self.handlers.insert(tool.function.name.clone(), handler);
+        self.tools.push(tool);
+        self
+    }
+
+    /// Gives up with [`Error::BudgetExceeded`] if the model has not answered after `max_iterations` completions.
+    pub fn max_iterations(mut self, max_iterations: u32) -> Self {
+        self.max_iterations = max_iterations.max(1);
+        self
+    }
+
+    /// Gives up with [`Error::BudgetExceeded`] if the model has not answered once its completions used `max_tokens` in total.
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Sets whether the tools called in the same message run at the same time, which they do by default.
+    /// Otherwise, they run one after the other, in the order they were called.
+    pub fn parallel(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
+    }
+
+    /// Calls `callback` with everything that happens while the agent runs, for example to log it.
+    pub fn on_event(mut self, callback: impl Fn(&AgentEvent) + Send + Sync + 'static) -> Self {
+        self.on_event = Some(Arc::new(callback));
+        self
+    }
+
+    /// Runs the chat of `builder` with the tools of the agent until the model answers.
+    /// The tools of the agent are added to those of `builder`, or to its functions if it has any.
+    pub async fn run(&self, builder: ChatCompletionBuilder) -> Result<AgentRun, Error> {
+        let mut request = builder.build()?;
+        let mut usage = Usage::default();
+
+        for tool in &self.tools {
+            if request.functions.is_empty() {
+                if !request
+                    .tools
+                    .iter()
+                    .any(|existing| existing.function.name == tool.function.name)
+                {
+                    request.tools.push(tool.clone());
+                }
+            } else if !request
+                .functions
+                .iter()
+                .any(|existing| existing.name == tool.function.name)
+            {
+                request.functions.push(tool.function.clone());
+            }
+        }
+
+        let mut iteration = 0;
+
+        loop {
+            iteration += 1;
+
+            let completion = ChatCompletion::create(&request).await?;
+            let message = completion
+                .choices
+                .into_iter()
+                .next()
+                .map(|choice| choice.message)
+                .ok_or_else(|| {
+                    Error::IncompleteResponse("the chat completion has no choices".to_string())
+                })?;
+
+            if let Some(completion_usage) = completion.usage {
+                usage.prompt_tokens += completion_usage.prompt_tokens;
+                usage.completion_tokens += completion_usage.completion_tokens;
+                usage.total_tokens += completion_usage.total_tokens;
+            }
+
+            self.emit(&AgentEvent::Completion {
+                iteration,
+                message: &message,
+                usage: completion.usage,
+            });
+            request.messages.push(message.clone());
+
+            if message.tool_calls.is_empty() && message.function_call.is_none() {
+                return Ok(AgentRun {
+                    message,
+                    messages: request.messages,
+                    iterations: iteration,
+                    usage,
+                });
+            }
+
+            if iteration >= self.max_iterations
+                || self
+                    .max_tokens
+                    .is_some_and(|max_tokens| usage.total_tokens >= max_tokens)
+            {
+                return Err(Error::BudgetExceeded {
+                    iterations: iteration,
+                    total_tokens: usage.total_tokens,
+                });
+            }
+
+            if let Some(function_call) = &message.function_call {
+                let call = ToolCall {
+                    id: String::new(),
+                    tool_type: ToolType::Function,
+                    function: function_call.clone(),
+                };
+                let content = match self.call(&call).await {
+                    Ok(content) => content,
+                    Err(error) => format!("Error: {error}"),
+                };
+
+                request.messages.push(ChatCompletionMessage {
+                    name: Some(call.function.name),
+                    ..ChatCompletionMessage::new(ChatCompletionMessageRole::Function, content)
+                });
+
+                continue;
+            }
+
+            let results = if self.parallel {
+                future::join_all(message.tool_calls.iter().map(|call| self.call(call))).await
+            } else {
+                let mut results = Vec::new();
+
+                for call in &message.tool_calls {
+                    results.push(self.call(call).await);
+                }
+
+                results
+            };
+
+            for (call, result) in message.tool_calls.iter().zip(results) {
+                let content = match result {
+                    Ok(content) => content,
+                    Err(error) => format!("Error: {error}"),
+                };
+
+                request
+                    .messages
+                    .push(ChatCompletionMessage::tool(&call.id, content));
+            }
+        }
+    }
+
+    async fn call(&self, call: &ToolCall) -> Result<String, String> {
+        self.emit(&AgentEvent::ToolCall { call });
+
+        let start = Instant::now();
+        let result = match self.handlers.get(&call.function.name) {
+            Some(handler) => handler(call.function.arguments.clone()).await,
+            None => Err(format!("there is no tool named `{}`", call.function.name)),
+        };
+
+        self.emit(&AgentEvent::ToolResult {
+            call,
+            result: &result,
+            duration: start.elapsed(),
+        });
+
+        result
+    }
+
+    fn emit(&self, event: &AgentEvent) {
+        if let Some(on_event) = &self.on_event {
+            on_event(event);
+        }
+    }
+}
+
+impl Default for Agent {
+    fn default() -> Self {
+        Agent::new()
+    }
+}
+
+impl std::fmt::Debug for Agent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Agent")
+            .field("tools", &self.tools)
+            .field("max_iterations", &self.max_iterations)
+            .field("max_tokens", &self.max_tokens)
+            .field("parallel", &self.parallel)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chat::ToolParameter,
+        stub::{StubResponse, StubServer},
+        OpenAiClient,
+    };
+    use serde::Deserialize;
+    use serde_json::{json, Value};
+    use std::sync::Mutex;
+
+    #[derive(Deserialize)]
+    struct GetWeather {
+        city: String,
+    }
+
+    impl ToolParameter for GetWeather {
+        fn schema() -> Value {
+            json!({
+                "type": "object",
+                "properties": { "city": String::schema() },
+                "required": ["city"],
+            })
+        }
+    }
+
+    impl OpenAiTool for GetWeather {
+        const NAME: &'static str = "get_weather";
+    }
+
+    fn completion(message: Value) -> StubResponse {
+        StubResponse::json(
+            200,
+            json!({
+                "id": "chatcmpl-123",
+                "object": "chat.completion",
+                "created": 1677652288,
+                "model": "gpt-3.5-turbo-0613",
+                "choices": [{"index": 0, "message": message, "finish_reason": "stop"}],
+                "usage": {"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15},
+            }),
+        )
+    }
+
+    fn tool_calls(calls: &[(&str, &str, &str)]) -> StubResponse {
+        let calls: Vec<Value> = calls
+            .iter()
+            .map(|(id, name, arguments)| {
+                json!({
+                    "id": id,
+                    "type": "function",
+                    "function": {"name": name, "arguments": arguments},
+                })
+            })
+            .collect();
+
+        completion(json!({"role": "assistant", "content": null, "tool_calls": calls}))
+    }
+
+    /// Returns a handler for `GetWeather` that records when its calls start and end.
+    fn logged_handler(
+        log: &Arc<Mutex<Vec<String>>>,
+    ) -> impl Fn(GetWeather) -> BoxFuture<'static, Result<String, String>> + Send + Sync {
+        let recorded = log.clone();
+
+        move |arguments: GetWeather| {
+            let log = recorded.clone();
+
+            async move {
+                log.lock()
+                    .unwrap()
+                    .push(format!("start {}", arguments.city));
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                log.lock().unwrap().push(format!("end {}", arguments.city));
+
+                Ok(format!("Sunny in {}", arguments.city))
+            }
+            .boxed()
+        }
+    }
+
+    fn question() -> ChatCompletionBuilder {
+        ChatCompletion::builder(
+            "gpt-3.5-turbo",
+            [ChatCompletionMessage::new(
+                ChatCompletionMessageRole::User,
+                "What's the weather in Paris and London?",
+            )],
+        )
+    }
+
+    #[tokio::test]
+    async fn runs_tools() {
+        let server = StubServer::start(vec![
+            tool_calls(&[
+                ("call_1", "get_weather", r#"{"city":"Paris"}"#),
+                ("call_2", "get_weather", r#"{"city":"London"}"#),
+                ("call_3", "get_weather", r#"{"town":"Rome"}"#),
+                ("call_4", "get_time", "{}"),
+            ]),
+            completion(json!({"role": "assistant", "content": "Sunny in both."})),
+        ])
+        .await;
+        let client = OpenAiClient::new("").base_url(server.url());
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let agent = Agent::new()
+            .tool(logged_handler(&log))
+            .on_event(move |event| {
+                recorded.lock().unwrap().push(match event {
+                    AgentEvent::Completion { iteration, .. } => format!("completion {iteration}"),
+                    AgentEvent::ToolCall { call } => format!("call {}", call.id),
+                    AgentEvent::ToolResult { call, result, .. } => {
+                        format!("result {} {}", call.id, result.is_ok())
+                    }
+                });
+            });
+        let run = agent.run(question().client(&client)).await.unwrap();
+
+        // Both calls started before either of them ended
+        assert_eq!(log.lock().unwrap()[..2], ["start Paris", "start London"]);
+        assert_eq!(run.message.content.as_deref(), Some("Sunny in both."));
+        assert_eq!(run.iterations, 2);
+        assert_eq!(run.usage.total_tokens, 30);
+        assert_eq!(run.messages.len(), 7);
+
+        let requests = server.requests();
+        let messages = &requests[1].json()["messages"];
+
+        assert_eq!(
+            requests[0].json()["tools"][0]["function"]["name"],
+            "get_weather"
+        );
+        assert_eq!(messages[2]["role"], "tool");
+        assert_eq!(messages[2]["tool_call_id"], "call_1");
+        assert_eq!(messages[2]["content"], "Sunny in Paris");
+        assert_eq!(messages[3]["content"], "Sunny in London");
+        assert!(messages[4]["content"]
+            .as_str()
+            .unwrap()
+            .starts_with("Error: invalid arguments"));
+        assert_eq!(
+            messages[5]["content"],
+            "Error: there is no tool named `get_time`"
+        );
+
+        let events = events.lock().unwrap();
+
+        assert_eq!(events[0], "completion 1");
+        assert_eq!(
+            events
+                .iter()
+                .filter(|event| event.starts_with("call"))
+                .count(),
+            4
+        );
+        assert!(events.contains(&"result call_1 true".to_string()));
+        assert!(events.contains(&"result call_4 false".to_string()));
+        assert_eq!(events.last().unwrap(), "completion 2");
+    }
+
+    #[tokio::test]
+    async fn runs_tools_in_order() {
+        let server = StubServer::start(vec![
+            tool_calls(&[
+                ("call_1", "get_weather", r#"{"city":"Paris"}"#),
+                ("call_2", "get_weather", r#"{"city":"London"}"#),
+            ]),
+            completion(json!({"role": "assistant", "content": "Sunny in both."})),
+        ])
+        .await;
+        let client = OpenAiClient::new("").base_url(server.url());
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let agent = Agent::new().parallel(false).tool(logged_handler(&log));
+
+        agent.run(question().client(&client)).await.unwrap();
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["start Paris", "end Paris", "start London", "end London"]
+        );
+    }
+
+    #[tokio::test]
+    async fn runs_functions() {
+        let server = StubServer::start(vec![
+            completion(json!({
+                "role": "assistant",
+                "content": null,
+                "function_call": {"name": "get_weather", "arguments": r#"{"city":"Paris"}"#},
+            })),
+            completion(json!({"role": "assistant", "content": "Sunny in Paris."})),
+        ])
+        .await;
+        let client = OpenAiClient::new("").base_url(server.url());
+        let agent = Agent::new().tool(|arguments: GetWeather| async move {
+            Ok::<_, String>(format!("Sunny in {}", arguments.city))
+        });
+        let run = agent
+            .run(
+                question()
+                    .functions(vec![GetWeather::tool().function])
+                    .client(&client),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(run.message.content.as_deref(), Some("Sunny in Paris."));
+        assert_eq!(run.iterations, 2);
+
+        let requests = server.requests();
+
+        assert_eq!(requests[0].json().get("tools"), None);
+        assert_eq!(requests[0].json()["functions"].as_array().unwrap().len(), 1);
+
+        let messages = &requests[1].json()["messages"];
+
+        assert_eq!(messages[2]["role"], "function");
+        assert_eq!(messages[2]["name"], "get_weather");
+        assert_eq!(messages[2]["content"], "Sunny in Paris");
+    }
+
+    #[tokio::test]
+    async fn budgets() {
+        let server = StubServer::start(vec![tool_calls(&[(
+            "call_1",
+            "get_weather",
+            r#"{"city":"Paris"}"#,
+        )])])
+        .await;
+        let client = OpenAiClient::new("").base_url(server.url());
+        let agent = Agent::new()
+            .max_iterations(3)
+            .tool(|_: GetWeather| async { Ok::<_, String>("Sunny".to_string()) });
+
+        match agent.run(question().client(&client)).await {
+            Err(Error::BudgetExceeded {
+                iterations,
+                total_tokens,
+            }) => {
+                assert_eq!(iterations, 3);
+                assert_eq!(total_tokens, 45);
+            }
+            _ => panic!("expected the budget to be exceeded"),
+        }
+
+        assert_eq!(server.requests().len(), 3);
+
+        let agent = agent.max_tokens(20);
+
+        assert!(matches!(
+            agent.run(question().client(&client)).await,
+            Err(Error::BudgetExceeded { iterations: 2, .. })
+        ));
+    }
+}
@@ -0,0 +1,484 @@
+use super::{
+    ChatCompletion, ChatCompletionBuilder, ChatCompletionMessage, ChatCompletionMessageRole,
+};
+use crate::{validate::context_window, Error, OpenAiClient, ValidationError};
+
+/// Tokens left for the reply of the model by the default budget of a [`Conversation`].
+const REPLY_TOKENS: u32 = 1024;
+
+/// What the model is asked to do when a [`Conversation`] is summarized, see [`Truncation::Summarize`].
+const SUMMARY_PROMPT: &str = "Summarize the conversation so far in a few sentences, \
+    keeping every fact, name and decision that may matter later on.";
+
+/// A chat with a model, which keeps the history of its messages and appends the replies of the model.
+///
+/// Before every request, older messages are truncated with a [`Truncation`] strategy
+/// so that the history fits in a budget of tokens. The system prompt is always kept.
+/// Tokens are counted exactly with the `tokenizer` feature, and estimated otherwise.
+///
+/// ## Examples
+///
+/// ```rust,no_run
+/// use openai::chat::{Conversation, Truncation};
+///
+/// # async fn run() -> Result<(), openai::Error> {
+/// let mut conversation = Conversation::new("gpt-3.5-turbo")
+///     .system("You are a helpful assistant.")
+///     .truncation(Truncation::KeepLast(20));
+/// let reply = conversation.send("Hello!").await?;
+///
+/// println!("{:?}", reply.content);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Conversation {
+    model: String,
+    system: Option<ChatCompletionMessage>,
+    messages: Vec<ChatCompletionMessage>,
+    token_budget: u32,
+    truncation: Truncation,
+    client: Option<OpenAiClient>,
+}
+
+/// How the history of a [`Conversation`] is shortened when it no longer fits in its budget of tokens.
+///
+/// Every strategy falls back to dropping the oldest messages if the history is still too long.
+/// The results of tool calls are dropped along with the message that called them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Truncation {
+    /// Drops the oldest messages until the history fits.
+    DropOldest,
+    /// Keeps only the last messages, even if older ones would fit.
+    KeepLast(usize),
+    /// Replaces the older messages with a summary written by the model, keeping the last `keep_last` messages as they are.
+    /// The summary counts towards the budget like other messages.
+    Summarize { keep_last: usize },
+}
+
+impl Conversation {
+    /// Starts a conversation with `model`, without a system prompt.
+    ///
+    /// The history is kept within the context window of the model (4096 tokens if it isn't known), minus 1024 tokens left for the reply,
+    /// by dropping the oldest messages.
+    pub fn new(model: impl Into<String>) -> Self {
+        let model = model.into();
+
+        Conversation {
+            token_budget: context_window(&model)
+                .unwrap_or(4096)
+                .saturating_sub(REPLY_TOKENS),
+            model,
+            system: None,
+            messages: Vec::new(),
+            truncation: Truncation::DropOldest,
+            client: None,
+        }
+    }
+
+    /// Sets the system prompt, which is sent before the history and never truncated.
+    pub fn system(mut self, content: impl Into<String>) -> Self {
+        self.system = Some(ChatCompletionMessage::new(
+            ChatCompletionMessageRole::System,
+            content,
+        ));
+        self
+    }
+
+    /// Sets how many tokens the system prompt and the history may use.
+    pub fn token_budget(mut self, token_budget: u32) -> Self {
+        self.token_budget = token_budget;
+        self
+    }
+
+    pub fn truncation(mut self, truncation: Truncation) -> Self {
+        self.truncation = truncation;
+        self
+    }
+
+    /// The client to make requests with.
+    /// Defaults to the client configured with [`set_key`](crate::set_key).
+    pub fn client(mut self, client: impl Into<OpenAiClient>) -> Self {
+        self.client = Some(client.into());
+        self
+    }
+
+    /// The messages of the conversation, without the system prompt.
+    pub fn messages(&self) -> &[ChatCompletionMessage] {
+        &self.messages
+    }
+
+    /// Appends `message` to the history without sending it, for example the result of a tool call.
+    pub fn push(&mut self, message: ChatCompletionMessage) {
+        self.messages.push(message);
+    }
+
+    /// Sends a user message with `content` and returns the reply of the model, which is appended to the history.
+    pub async fn send(
+        &mut self,
+        content: impl Into<String>,
+    ) -> Result<ChatCompletionMessage, Error> {
+        self.push(ChatCompletionMessage::new(
+            ChatCompletionMessageRole::User,
+            content,
+        ));
+        self.reply(|builder| builder).await
+    }
+
+    /// Asks the model to reply to the history as it is, with a request set up by `configure`,
+    /// and returns the reply, which is appended to the history.
+    pub async fn reply(
+        &mut self,
+        configure: impl FnOnce(ChatCompletionBuilder) -> ChatCompletionBuilder,
+    ) -> Result<ChatCompletionMessage, Error> {
+        self.truncate().await?;
+
+        let message = first_message(configure(self.builder(self.history())).create().await?)?;
+
+        self.messages.push(message.clone());
+
+        Ok(message)
+    }
+
+    /// Shortens the history with the truncation strategy until it fits in the budget of tokens.
+    /// This is done before every request.
+    ///
+    /// The last message is never dropped, so this fails with [`ValidationError::TokenBudgetExceeded`]
+    /// if it doesn't fit in the budget along with the system prompt.
+    pub async fn truncate(&mut self) -> Result<(), Error> {
+        match self.truncation {
+            Truncation::DropOldest => {}
+            Truncation::KeepLast(keep_last) => {
+                while self.messages.len() > keep_last && self.can_drop_oldest() {
+                    self.drop_oldest();
+                }
+            }
+            Truncation::Summarize { keep_last } => {
+                if !self.fits() && self.messages.len() > keep_last.max(1) {
+                    self.summarize(keep_last.max(1)).await?;
+                }
+            }
+        }
+
+        while !self.fits() && self.can_drop_oldest() {
+            self.drop_oldest();
+        }
+
+        let tokens = count_tokens(&self.model, &self.history());
+
+        if tokens > self.token_budget {
+            return Err(ValidationError::TokenBudgetExceeded {
+                tokens,
+                token_budget: self.token_budget,
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Replaces every message but the last `keep_last` with a summary written by the model.
+    async fn summarize(&mut self, keep_last: usize) -> Result<(), Error> {
+        let mut split = self.messages.len() - keep_last;
+
+        // Tool results can't be separated from the message calling them
+        while split < self.messages.len()
+            && matches!(self.messages[split].role, ChatCompletionMessageRole::Tool)
+        {
+            split += 1;
+        }
+
+        if split == self.messages.len() {
+            return Ok(());
+        }
+
+        let mut messages = self.history()[..self.system.iter().len() + split].to_vec();
+
+        messages.push(ChatCompletionMessage::new(
+            ChatCompletionMessageRole::User,
+            SUMMARY_PROMPT,
+        ));
+
+        let summary = first_message(self.builder(messages).create().await?)?;
+        let summary = ChatCompletionMessage::new(
+            ChatCompletionMessageRole::System,
+            format!(
+                "Summary of the earlier conversation: {}",
+                summary.content.unwrap_or_default()
+            ),
+        );
+
+        self.messages.splice(..split, [summary]);
+
+        Ok(())
+    }
+
+    fn drop_oldest(&mut self) {
+        self.messages.remove(0);
+
+        while self
+            .messages
+            .first()
+            .is_some_and(|message| matches!(message.role, ChatCompletionMessageRole::Tool))
+        {
+            self.messages.remove(0);
+        }
+    }
+
+    /// Whether dropping the oldest message, and the results of the tools it called, would keep the last message.
+    fn can_drop_oldest(&self) -> bool {
+        self.messages
+            .iter()
+            .skip(1)
+            .any(|message| !matches!(message.role, ChatCompletionMessageRole::Tool))
+    }
+
+    fn fits(&self) -> bool {
+        count_tokens(&self.model, &self.history()) <= self.token_budget
+    }
+
+    /// Returns the system prompt followed by the messages.
+    fn history(&self) -> Vec<ChatCompletionMessage> {
+        self.system.iter().chain(&self.messages).cloned().collect()
+    }
+
+    fn builder(&self, messages: Vec<ChatCompletionMessage>) -> ChatCompletionBuilder {
+        let builder = ChatCompletion::builder(&self.model, messages);
+
+        match &self.client {
+            Some(client) => builder.client(client),
+            None => builder,
+        }
+    }
+}
+
+fn first_message(chat_completion: ChatCompletion) -> Result<ChatCompletionMessage, Error> {
+    chat_completion
+        .choices
+        .into_iter()
+        .next()
+        .map(|choice| choice.message)
+        .ok_or_else(|| Error::IncompleteResponse("the chat completion has no choices".to_string()))
+}
+
+/// Counts the tokens of a chat with the `tokenizer` feature and a known model.
+/// Otherwise, estimates them with about 4 characters per token plus the overhead of every message.
+#[cfg_attr(not(feature = "tokenizer"), allow(unused_variables))]
+fn count_tokens(model: &str, messages: &[ChatCompletionMessage]) -> u32 {
+    #[cfg(feature = "tokenizer")]
+    if let Some(tokens) = crate::tokenizer::count_chat_tokens(model, messages) {
+        return tokens as u32;
+    }
+
+    let characters: usize = messages
+        .iter()
+        .map(|message| {
+            let calls: usize = message
+                .tool_calls
+                .iter()
+                .map(|call| call.function.name.len() + call.function.arguments.len())
+                .sum();
+
+            message.content.as_deref().map_or(0, str::len) + calls
+        })
+        .sum();
+
+    (characters / 4) as u32 + messages.len() as u32 * 4 + 3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub::{StubResponse, StubServer};
+    use serde_json::json;
+
+    fn answer(content: &str) -> StubResponse {
+        StubResponse::json(
+            200,
+            json!({
+                "id": "chatcmpl-123",
+                "object": "chat.completion",
+                "created": 1677652288,
+                "model": "gpt-3.5-turbo-0301",
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": content},
+                    "finish_reason": "stop",
+                }],
+            }),
+        )
+    }
+
+    fn contents(messages: &serde_json::Value) -> Vec<&str> {
+        messages
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|message| message["content"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn conversations() {
+        let server = StubServer::start(vec![answer("Hi!"), answer("Fine.")]).await;
+        let client = OpenAiClient::new("").base_url(server.url());
+        let mut conversation = Conversation::new("gpt-3.5-turbo")
+            .system("Be brief.")
+            .client(&client);
+
+        conversation.send("Hello!").await.unwrap();
+
+        let reply = conversation.send("How are you?").await.unwrap();
+
+        assert_eq!(reply.content.as_deref(), Some("Fine."));
+        assert_eq!(conversation.messages().len(), 4);
+        assert_eq!(
+            contents(&server.requests()[1].json()["messages"]),
+            ["Be brief.", "Hello!", "Hi!", "How are you?"]
+        );
+    }
+
+    #[tokio::test]
+    async fn truncation() {
+        let server = StubServer::start(vec![answer("Fine.")]).await;
+        let client = OpenAiClient::new("").base_url(server.url());
+        let mut conversation = Conversation::new("gpt-3.5-turbo")
+            .system("Be brief.")
+            .client(&client)
+            .token_budget(30);
+
+        for content in ["One", "Two", "Three", "Four", "Five"] {
+            conversation.push(ChatCompletionMessage::new(
+                ChatCompletionMessageRole::User,
+                content,
+            ));
+        }
+
+        conversation.send("Six").await.unwrap();
+
+        // Every message costs 5 tokens, on top of 3 for the reply
+        assert_eq!(
+            contents(&server.requests()[0].json()["messages"]),
+            ["Be brief.", "Three", "Four", "Five", "Six"]
+        );
+
+        let mut conversation = conversation
+            .token_budget(1000)
+            .truncation(Truncation::KeepLast(2));
+
+        conversation.send("Seven").await.unwrap();
+
+        assert_eq!(
+            contents(&server.requests()[1].json()["messages"]),
+            ["Be brief.", "Fine.", "Seven"]
+        );
+    }
+
+    #[tokio::test]
+    async fn tool_results_are_dropped_with_their_call() {
+        let mut conversation = Conversation::new("gpt-3.5-turbo").token_budget(20);
+        let mut call: ChatCompletionMessage = serde_json::from_value(json!({
+            "role": "assistant",
+            "tool_calls": [{
+                "id": "call_1",
+                "type": "function",
+                "function": {"name": "get_time", "arguments": "{}"},
+            }],
+        }))
+        .unwrap();
+
+        call.content = None;
+        conversation.push(call);
+        conversation.push(ChatCompletionMessage::tool("call_1", "12:00"));
+        conversation.push(ChatCompletionMessage::new(
+            ChatCompletionMessageRole::Assistant,
+            "It is noon.",
+        ));
+        conversation.truncate().await.unwrap();
+
+        assert_eq!(conversation.messages().len(), 1);
+        assert!(matches!(
+            conversation.messages()[0].role,
+            ChatCompletionMessageRole::Assistant
+        ));
+    }
+
+    #[tokio::test]
+    async fn last_message_is_kept() {
+        let server = StubServer::start(vec![StubResponse::json(
+            200,
+            json!({
+                "id": "chatcmpl-123",
+                "object": "chat.completion",
+                "created": 1677652288,
+                "model": "gpt-3.5-turbo-0301",
+                "choices": [],
+            }),
+        )])
+        .await;
+        let client = OpenAiClient::new("").base_url(server.url());
+        let mut conversation = Conversation::new("gpt-3.5-turbo")
+            .system("Be brief.")
+            .client(&client)
+            .token_budget(30);
+        let result = conversation.send("Hello! ".repeat(20)).await;
+
+        assert!(matches!(
+            result,
+            Err(Error::Validation(ValidationError::TokenBudgetExceeded {
+                token_budget: 30,
+                ..
+            }))
+        ));
+        assert!(server.requests().is_empty());
+        assert_eq!(conversation.messages().len(), 1);
+
+        let mut conversation = conversation
+            .token_budget(1000)
+            .truncation(Truncation::KeepLast(0));
+
+        assert!(matches!(
+            conversation.send("Hello!").await,
+            Err(Error::IncompleteResponse(_))
+        ));
+        assert_eq!(
+            contents(&server.requests()[0].json()["messages"]),
+            ["Be brief.", "Hello!"]
+        );
+    }
+
+    #[tokio::test]
+    async fn summaries() {
+        let server =
+            StubServer::start(vec![answer("The user counted to five."), answer("Six!")]).await;
+        let client = OpenAiClient::new("").base_url(server.url());
+        let mut conversation = Conversation::new("gpt-3.5-turbo")
+            .client(&client)
+            .token_budget(30)
+            .truncation(Truncation::Summarize { keep_last: 1 });
+
+        for content in ["One", "Two", "Three", "Four", "Five"] {
+            conversation.push(ChatCompletionMessage::new(
+                ChatCompletionMessageRole::User,
+                content,
+            ));
+        }
+
+        conversation.send("What comes next?").await.unwrap();
+
+        let requests = server.requests();
+
+        assert_eq!(
+            contents(&requests[0].json()["messages"]),
+            ["One", "Two", "Three", "Four", "Five", SUMMARY_PROMPT]
+        );
+        assert_eq!(
+            contents(&requests[1].json()["messages"]),
+            [
+                "Summary of the earlier conversation: The user counted to five.",
+                "What comes next?"
+            ]
+        );
+        assert_eq!(conversation.messages().len(), 3);
+    }
+}
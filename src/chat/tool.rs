@@ -0,0 +1,161 @@
+use super::{Function, Tool, ToolType};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+
+/// The arguments of a function that the model can call, see [`ChatCompletionBuilder::tools`](super::ChatCompletionBuilder::tools).
+///
+/// With the `derive` feature, this can be derived for structs.
+/// The function is named after the struct in snake case, unless named with `#[tool(name = "...")]`,
+/// and is described by the doc comment of the struct. The doc comments of fields describe the parameters.
+pub trait OpenAiTool: ToolParameter + DeserializeOwned {
+    const NAME: &'static str;
+    const DESCRIPTION: Option<&'static str> = None;
+
+    fn tool() -> Tool {
+        Tool {
+            tool_type: ToolType::Function,
+            function: Function {
+                name: Self::NAME.to_string(),
+                description: Self::DESCRIPTION.map(str::to_string),
+                parameters: Self::schema(),
+            },
+        }
+    }
+
+    /// Reads the arguments of a call of the function, which were generated by the model.
+    fn from_arguments(arguments: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(arguments)
+    }
+}
+
+/// A type that the arguments of a tool call can hold, described by a JSON Schema.
+///
+/// With the `derive` feature, this can be derived for structs with named fields and for enums whose variants have no fields.
+pub trait ToolParameter {
+    /// Whether fields of this type must be given by the model. Only optional types are not required.
+    const REQUIRED: bool = true;
+
+    fn schema() -> Value;
+}
+
+macro_rules! impl_tool_parameter {
+    ($schema:expr => $($ty:ty),+) => {
+        $(
+            impl ToolParameter for $ty {
+                fn schema() -> Value {
+                    $schema
+                }
+            }
+        )+
+    };
+}
+
+impl_tool_parameter!(json!({ "type": "string" }) => String, char);
+impl_tool_parameter!(json!({ "type": "boolean" }) => bool);
+impl_tool_parameter!(json!({ "type": "integer" }) => i8, i16, i32, i64, i128, isize);
+impl_tool_parameter!(json!({ "type": "integer", "minimum": 0 }) => u8, u16, u32, u64, u128, usize);
+impl_tool_parameter!(json!({ "type": "number" }) => f32, f64);
+impl_tool_parameter!(json!({}) => Value);
+
+impl<T: ToolParameter> ToolParameter for Option<T> {
+    const REQUIRED: bool = false;
+
+    fn schema() -> Value {
+        T::schema()
+    }
+}
+
+impl<T: ToolParameter> ToolParameter for Box<T> {
+    const REQUIRED: bool = T::REQUIRED;
+
+    fn schema() -> Value {
+        T::schema()
+    }
+}
+
+macro_rules! impl_tool_parameter_array {
+    ($($ty:ident),+) => {
+        $(
+            impl<T: ToolParameter> ToolParameter for $ty<T> {
+                fn schema() -> Value {
+                    json!({ "type": "array", "items": T::schema() })
+                }
+            }
+        )+
+    };
+}
+
+impl_tool_parameter_array!(Vec, VecDeque, HashSet, BTreeSet);
+
+macro_rules! impl_tool_parameter_map {
+    ($($ty:ident),+) => {
+        $(
+            impl<T: ToolParameter> ToolParameter for $ty<String, T> {
+                fn schema() -> Value {
+                    json!({ "type": "object", "additionalProperties": T::schema() })
+                }
+            }
+        )+
+    };
+}
+
+impl_tool_parameter_map!(HashMap, BTreeMap);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    /// Gets the current weather of a city.
+    #[derive(Deserialize)]
+    struct GetWeather {
+        city: String,
+    }
+
+    impl ToolParameter for GetWeather {
+        fn schema() -> Value {
+            json!({
+                "type": "object",
+                "properties": { "city": String::schema() },
+                "required": ["city"],
+            })
+        }
+    }
+
+    impl OpenAiTool for GetWeather {
+        const NAME: &'static str = "get_weather";
+        const DESCRIPTION: Option<&'static str> = Some("Gets the current weather of a city.");
+    }
+
+    #[test]
+    fn schemas() {
+        assert_eq!(
+            Vec::<Option<u8>>::schema(),
+            json!({ "type": "array", "items": { "type": "integer", "minimum": 0 } })
+        );
+        assert_eq!(
+            HashMap::<String, f64>::schema(),
+            json!({ "type": "object", "additionalProperties": { "type": "number" } })
+        );
+    }
+
+    #[test]
+    fn tools() {
+        let tool = GetWeather::tool();
+
+        assert_eq!(tool.function.name, "get_weather");
+        assert_eq!(
+            tool.function.description.as_deref(),
+            Some("Gets the current weather of a city.")
+        );
+        assert_eq!(tool.function.parameters["required"], json!(["city"]));
+        assert_eq!(
+            GetWeather::from_arguments(r#"{"city":"Paris"}"#)
+                .unwrap()
+                .city,
+            "Paris"
+        );
+        assert!(GetWeather::from_arguments(r#"{"town":"Paris"}"#).is_err());
+    }
+}
@@ -1,8 +1,13 @@
 //! Given a prompt, the model will return one or more predicted completions,
 //! and can also return the probabilities of alternative tokens at each position.
 
-use super::{openai_post, ApiResponseOrError, Usage};
+use super::{
+    openai_post, openai_post_stream, perplexity, request_client,
+    validate::{check_context, check_logit_bias, check_range, check_stop},
+    Error, EventStream, LogitBias, OpenAiClient, Response, Usage, Validate, ValidationError,
+};
 use derive_builder::Builder;
+use futures_util::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -12,21 +17,118 @@ pub struct Completion {
     pub created: u32,
     pub model: String,
     pub choices: Vec<CompletionChoice>,
-    pub usage: Usage,
+    /// Not sent when the completion is streamed.
+    pub usage: Option<Usage>,
 }
 
 #[derive(Deserialize, Clone)]
 pub struct CompletionChoice {
     pub text: String,
     pub index: u16,
-    pub logprobs: Option<u16>,
-    pub finish_reason: String,
+    pub logprobs: Option<Logprobs>,
+    /// Not sent until the last chunk of a streamed choice.
+    pub finish_reason: Option<String>,
+}
+
+/// The log probabilities of the tokens in a [`CompletionChoice`], requested with `logprobs`.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct Logprobs {
+    pub tokens: Vec<String>,
+    /// The log probability of each token in `tokens`.
+    /// This is `None` for the first token of an echoed prompt.
+    pub token_logprobs: Vec<Option<f64>>,
+    /// The `logprobs` most likely tokens at each position, mapped to their log probabilities.
+    pub top_logprobs: Vec<Option<HashMap<String, f64>>>,
+    /// The character offset of each token in the completion text.
+    pub text_offset: Vec<u32>,
+}
+
+/// The prompt(s) of a [`CompletionRequest`].
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Prompt {
+    Text(String),
+    Texts(Vec<String>),
+    /// A prompt given as token IDs, in the tokenizer of the model.
+    Tokens(Vec<u32>),
+    /// Several prompts given as token IDs, in the tokenizer of the model.
+    TokenArrays(Vec<Vec<u32>>),
+}
+
+impl Prompt {
+    /// Returns the number of prompts.
+    pub fn len(&self) -> usize {
+        match self {
+            Prompt::Text(_) | Prompt::Tokens(_) => 1,
+            Prompt::Texts(texts) => texts.len(),
+            Prompt::TokenArrays(token_arrays) => token_arrays.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl From<String> for Prompt {
+    fn from(text: String) -> Self {
+        Prompt::Text(text)
+    }
+}
+
+impl From<&str> for Prompt {
+    fn from(text: &str) -> Self {
+        Prompt::Text(text.to_string())
+    }
+}
+
+impl From<&String> for Prompt {
+    fn from(text: &String) -> Self {
+        Prompt::Text(text.clone())
+    }
+}
+
+impl From<Vec<String>> for Prompt {
+    fn from(texts: Vec<String>) -> Self {
+        Prompt::Texts(texts)
+    }
+}
+
+impl From<Vec<&str>> for Prompt {
+    fn from(texts: Vec<&str>) -> Self {
+        Prompt::Texts(texts.into_iter().map(str::to_string).collect())
+    }
+}
+
+impl<const N: usize> From<[&str; N]> for Prompt {
+    fn from(texts: [&str; N]) -> Self {
+        Prompt::Texts(texts.into_iter().map(str::to_string).collect())
+    }
+}
+
+impl From<Vec<u32>> for Prompt {
+    fn from(tokens: Vec<u32>) -> Self {
+        Prompt::Tokens(tokens)
+    }
+}
+
+impl From<&[u32]> for Prompt {
+    fn from(tokens: &[u32]) -> Self {
+        Prompt::Tokens(tokens.to_vec())
+    }
+}
+
+impl From<Vec<Vec<u32>>> for Prompt {
+    fn from(token_arrays: Vec<Vec<u32>>) -> Self {
+        Prompt::TokenArrays(token_arrays)
+    }
 }
 
 #[derive(Serialize, Builder, Debug, Clone)]
 #[builder(pattern = "owned")]
 #[builder(name = "CompletionBuilder")]
 #[builder(setter(strip_option, into))]
+#[builder(build_fn(error = "Error"))]
 pub struct CompletionRequest {
     /// ID of the model to use.
     /// You can use the [List models](https://beta.openai.com/docs/api-reference/models/list)
@@ -39,9 +141,11 @@ pub struct CompletionRequest {
     ///
     /// Note that <|endoftext|> is the document separator that the model sees during training,
     /// so if a prompt is not specified the model will generate as if from the beginning of a new document.
+    ///
+    /// Every prompt gets `n` choices, which can be grouped back by prompt with [`Completion::choices_by_prompt`].
     #[serde(skip_serializing_if = "Option::is_none")]
     #[builder(default)]
-    pub prompt: Option<String>,
+    pub prompt: Option<Prompt>,
     /// The suffix that comes after a completion of inserted text.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[builder(default)]
@@ -80,7 +184,7 @@ pub struct CompletionRequest {
     /// [server-sent events](https://developer.mozilla.org/en-US/docs/Web/API/Server-sent_events/Using_server-sent_events#Event_stream_format)
     /// as they become available, with the stream terminated by a `data: [DONE]` message.
     #[serde(skip_serializing_if = "Option::is_none")]
-    #[builder(setter(skip), default)] // skipped until properly implemented
+    #[builder(setter(skip), default)] // set by `CompletionBuilder::create_stream`
     pub stream: Option<bool>,
     /// Include the log probabilities on the logprobs most likely tokens, as well the chosen tokens.
     /// For example, if logprobs is 5, the API will return a list of the 5 most likely tokens.
@@ -107,7 +211,7 @@ pub struct CompletionRequest {
     /// [See more information about frequency and presence penalties](https://beta.openai.com/docs/api-reference/parameter-details).
     #[serde(skip_serializing_if = "Option::is_none")]
     #[builder(default)]
-    pub presence_penalty: Option<i8>,
+    pub presence_penalty: Option<f32>,
     /// Number between -2.0 and 2.0.
     /// Positive values penalize new tokens based on their existing frequency in the text so far,
     /// decreasing the model's likelihood to repeat the same line verbatim.
@@ -115,7 +219,7 @@ pub struct CompletionRequest {
     /// [See more information about frequency and presence penalties](https://beta.openai.com/docs/api-reference/parameter-details).
     #[serde(skip_serializing_if = "Option::is_none")]
     #[builder(default)]
-    pub frequency_penalty: Option<i8>,
+    pub frequency_penalty: Option<f32>,
     /// Generates `best_of` completions server-side and returns the "best" (the one with the highest log probability per token).
     /// Results cannot be streamed.
     ///
@@ -129,45 +233,275 @@ pub struct CompletionRequest {
     pub best_of: Option<u16>,
     /// Modify the likelihood of specified tokens appearing in the completion.
     ///
-    /// Accepts a json object that maps tokens (specified by their token ID in the GPT tokenizer) to an associated bias value from -100 to 100.
-    /// You can use this [tokenizer tool](https://beta.openai.com/tokenizer?view=bpe) (which works for both GPT-2 and GPT-3) to convert text to token IDs.
+    /// Maps tokens (specified by their token ID in the GPT tokenizer) to an associated bias value from -100 to 100.
+    /// You can use this [tokenizer tool](https://beta.openai.com/tokenizer?view=bpe) (which works for both GPT-2 and GPT-3) to convert text to token IDs,
+    /// or [`LogitBias::word`] with the `tokenizer` feature.
     /// Mathematically, the bias is added to the logits generated by the model prior to sampling.
     /// The exact effect will vary per model, but values between -1 and 1 should decrease or increase likelihood of selection;
     /// values like -100 or 100 should result in a ban or exclusive selection of the relevant token.
     ///
-    /// As an example, you can pass `{"50256": -100}` to prevent the <|endoftext|> token from being generated.
-    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    /// As an example, you can pass `LogitBias::from([(50256, -100.0)])` to prevent the <|endoftext|> token from being generated.
+    #[serde(skip_serializing_if = "LogitBias::is_empty")]
     #[builder(default)]
-    pub logit_bias: HashMap<String, i16>,
+    pub logit_bias: LogitBias,
     /// A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse.
     /// [Learn more](https://beta.openai.com/docs/guides/safety-best-practices/end-user-ids).
     #[serde(skip_serializing_if = "Option::is_none")]
     #[builder(default)]
     pub user: Option<String>,
+    /// The client to make the request with.
+    /// Defaults to the client configured with [`set_key`](crate::set_key).
+    #[serde(skip)]
+    #[builder(default)]
+    pub client: Option<OpenAiClient>,
+    /// The organization the request counts towards, instead of that of its client.
+    #[serde(skip)]
+    #[builder(default)]
+    pub organization: Option<String>,
+    /// The project the request counts towards, instead of that of its client.
+    #[serde(skip)]
+    #[builder(default)]
+    pub project: Option<String>,
 }
 
 impl Completion {
     /// Creates a completion for the provided prompt and parameters
-    async fn create(request: &CompletionRequest) -> ApiResponseOrError<Self> {
-        openai_post("completions", request).await
+    async fn create(request: &CompletionRequest) -> Result<Response<Self>, Error> {
+        request.validate()?;
+
+        let client = request_client(
+            request.client.as_ref(),
+            request.organization.as_ref(),
+            request.project.as_ref(),
+        );
+
+        openai_post(client.as_ref(), "completions", request).await
     }
 
     pub fn builder(model: &str) -> CompletionBuilder {
         CompletionBuilder::create_empty().model(model)
     }
+
+    /// Collects the chunks of a streamed completion back into one completion,
+    /// as if it had not been streamed.
+    pub async fn from_stream<S>(mut stream: S) -> Result<Self, Error>
+    where
+        S: Stream<Item = Result<Self, Error>> + Unpin,
+    {
+        let mut completion: Option<Self> = None;
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+
+            match &mut completion {
+                Some(completion) => completion.merge(chunk),
+                None => completion = Some(chunk),
+            }
+        }
+
+        completion.ok_or_else(|| {
+            Error::IncompleteResponse(
+                "the stream ended before a completion was received".to_string(),
+            )
+        })
+    }
+
+    /// Groups the choices by the prompt they were generated for, in the order of the prompts,
+    /// given the number of choices `n` requested for every prompt (1 by default).
+    ///
+    /// The API numbers the `n` choices of the first prompt from 0, followed by those of the second prompt, and so on.
+    pub fn choices_by_prompt(&self, n: u16) -> Vec<Vec<&CompletionChoice>> {
+        let n = n.max(1);
+        let mut prompts: Vec<Vec<&CompletionChoice>> = Vec::new();
+
+        for choice in &self.choices {
+            let prompt = (choice.index / n) as usize;
+
+            if prompts.len() <= prompt {
+                prompts.resize_with(prompt + 1, Vec::new);
+            }
+
+            prompts[prompt].push(choice);
+        }
+
+        for choices in &mut prompts {
+            choices.sort_by_key(|choice| choice.index);
+        }
+
+        prompts
+    }
+
+    /// Appends the choices of a later chunk of the same streamed completion.
+    pub fn merge(&mut self, chunk: Self) {
+        for choice in chunk.choices {
+            match self
+                .choices
+                .iter_mut()
+                .find(|existing| existing.index == choice.index)
+            {
+                Some(existing) => existing.merge(choice),
+                None => self.choices.push(choice),
+            }
+        }
+
+        self.choices.sort_by_key(|choice| choice.index);
+
+        if chunk.usage.is_some() {
+            self.usage = chunk.usage;
+        }
+    }
+}
+
+impl CompletionChoice {
+    fn merge(&mut self, chunk: Self) {
+        self.text.push_str(&chunk.text);
+
+        if let Some(logprobs) = chunk.logprobs {
+            self.logprobs
+                .get_or_insert_with(Default::default)
+                .extend(logprobs);
+        }
+
+        if chunk.finish_reason.is_some() {
+            self.finish_reason = chunk.finish_reason;
+        }
+    }
+}
+
+impl Logprobs {
+    /// Returns the perplexity of the tokens, see [`ChatCompletionLogprobs::perplexity`](crate::chat::ChatCompletionLogprobs::perplexity).
+    /// Tokens without a log probability are left out.
+    pub fn perplexity(&self) -> Option<f64> {
+        perplexity(self.token_logprobs.iter().flatten().copied())
+    }
+
+    /// Returns the most likely tokens at `position` with their log probabilities, from most to least likely.
+    /// This is empty if the position is out of bounds or has no alternatives.
+    pub fn top_alternatives(&self, position: usize) -> Vec<(&str, f64)> {
+        let mut alternatives: Vec<(&str, f64)> = self
+            .top_logprobs
+            .get(position)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|(token, logprob)| (token.as_str(), *logprob))
+            .collect();
+
+        alternatives.sort_by(|a, b| b.1.total_cmp(&a.1));
+        alternatives
+    }
+
+    fn extend(&mut self, other: Self) {
+        self.tokens.extend(other.tokens);
+        self.token_logprobs.extend(other.token_logprobs);
+        self.top_logprobs.extend(other.top_logprobs);
+        self.text_offset.extend(other.text_offset);
+    }
+}
+
+impl Validate for CompletionRequest {
+    fn validate(&self) -> Result<(), ValidationError> {
+        check_range("temperature", self.temperature, 0.0, 2.0)?;
+        check_range("top_p", self.top_p, 0.0, 1.0)?;
+        check_range("presence_penalty", self.presence_penalty, -2.0, 2.0)?;
+        check_range("frequency_penalty", self.frequency_penalty, -2.0, 2.0)?;
+        check_range("logprobs", self.logprobs, 0.0, 5.0)?;
+        check_stop(&self.stop)?;
+        check_logit_bias(&self.logit_bias)?;
+
+        if let (Some(best_of), Some(n)) = (self.best_of, self.n) {
+            if best_of <= n {
+                return Err(ValidationError::BestOfNotGreaterThanN { best_of, n });
+            }
+        }
+
+        if let Some(max_tokens) = self.max_tokens {
+            check_context(
+                &self.model,
+                prompt_tokens(&self.model, self.prompt.as_ref()),
+                max_tokens.into(),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Counts the tokens of the longest prompt, with the `tokenizer` feature.
+#[cfg_attr(not(feature = "tokenizer"), allow(unused_variables))]
+fn prompt_tokens(model: &str, prompt: Option<&Prompt>) -> u32 {
+    let tokens = match prompt {
+        Some(Prompt::Tokens(tokens)) => tokens.len(),
+        Some(Prompt::TokenArrays(token_arrays)) => {
+            token_arrays.iter().map(Vec::len).max().unwrap_or(0)
+        }
+        #[cfg(feature = "tokenizer")]
+        Some(Prompt::Text(text)) => {
+            crate::tokenizer::Encoding::for_model(model).map_or(0, |encoding| encoding.count(text))
+        }
+        #[cfg(feature = "tokenizer")]
+        Some(Prompt::Texts(texts)) => {
+            crate::tokenizer::Encoding::for_model(model).map_or(0, |encoding| {
+                texts
+                    .iter()
+                    .map(|text| encoding.count(text))
+                    .max()
+                    .unwrap_or(0)
+            })
+        }
+        _ => 0,
+    };
+
+    tokens as u32
 }
 
 impl CompletionBuilder {
-    pub async fn create(self) -> ApiResponseOrError<Completion> {
-        Completion::create(&self.build().unwrap()).await
+    pub async fn create(self) -> Result<Completion, Error> {
+        Ok(self.create_with_metadata().await?.body)
+    }
+
+    /// Like [`CompletionBuilder::create`], but the metadata of the response is returned too.
+    pub async fn create_with_metadata(self) -> Result<Response<Completion>, Error> {
+        Completion::create(&self.build()?).await
+    }
+
+    /// Like [`CompletionBuilder::create`], but the completion is streamed back in chunks as it is generated.
+    /// Each chunk holds the text generated since the previous chunk for one or more choices, see [`CompletionChoice::index`].
+    ///
+    /// Use [`Completion::from_stream`] to get the full completion once the stream ends.
+    pub async fn create_stream(self) -> Result<EventStream<Completion>, Error> {
+        Ok(self.create_stream_with_metadata().await?.body)
+    }
+
+    /// Like [`CompletionBuilder::create_stream`], but the metadata of the response is returned too.
+    pub async fn create_stream_with_metadata(
+        self,
+    ) -> Result<Response<EventStream<Completion>>, Error> {
+        let mut request = self.build()?;
+
+        request.validate()?;
+        request.stream = Some(true);
+
+        let client = request_client(
+            request.client.as_ref(),
+            request.organization.as_ref(),
+            request.project.as_ref(),
+        );
+
+        openai_post_stream(client.as_ref(), "completions", &request).await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::set_key;
+    use crate::{
+        set_key,
+        stub::{StubResponse, StubServer},
+    };
     use dotenvy::dotenv;
+    use futures_util::stream;
+    use serde_json::json;
     use std::env;
 
     #[tokio::test]
@@ -181,7 +515,6 @@ mod tests {
             .temperature(0.0)
             .create()
             .await
-            .unwrap()
             .unwrap();
 
         assert_eq!(
@@ -189,4 +522,143 @@ mod tests {
             "\n\nThis is indeed a test"
         );
     }
+
+    #[tokio::test]
+    async fn completion_stub() {
+        let server = StubServer::start(vec![StubResponse::json(
+            200,
+            json!({
+                "id": "cmpl-123",
+                "object": "text_completion",
+                "created": 1589478378,
+                "model": "text-davinci-003",
+                "choices": [{
+                    "text": "\n\nThis is indeed a test",
+                    "index": 0,
+                    "logprobs": null,
+                    "finish_reason": "length",
+                }],
+                "usage": {"prompt_tokens": 5, "completion_tokens": 7, "total_tokens": 12},
+            }),
+        )])
+        .await;
+        let client = OpenAiClient::new("key").base_url(server.url());
+        let completion = Completion::builder("text-davinci-003")
+            .prompt("Say this is a test")
+            .max_tokens(7)
+            .logit_bias([(50256, -100.0)])
+            .client(&client)
+            .create()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            completion.choices.first().unwrap().text,
+            "\n\nThis is indeed a test"
+        );
+
+        let request = &server.requests()[0];
+
+        assert_eq!(request.path, "/v1/completions");
+        assert_eq!(request.json()["max_tokens"], 7);
+        assert_eq!(request.json()["logit_bias"], json!({ "50256": -100.0 }));
+
+        let result = Completion::builder("text-davinci-003")
+            .logit_bias([(50256, -200.0)])
+            .client(&client)
+            .create()
+            .await;
+
+        assert!(matches!(result, Err(Error::Validation(_))));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn completion_prompts() {
+        let choice = |text: &str, index: u16| json!({ "text": text, "index": index, "logprobs": null, "finish_reason": "length" });
+        let server = StubServer::start(vec![StubResponse::json(
+            200,
+            json!({
+                "id": "cmpl-123",
+                "object": "text_completion",
+                "created": 1589478378,
+                "model": "text-davinci-003",
+                "choices": [choice("b", 3), choice("a", 0), choice("B", 2), choice("A", 1)],
+            }),
+        )])
+        .await;
+        let client = OpenAiClient::new("key").base_url(server.url());
+        let completion = Completion::builder("text-davinci-003")
+            .prompt(["Say a", "Say b"])
+            .n(2u16)
+            .client(&client)
+            .create()
+            .await
+            .unwrap();
+        let texts: Vec<Vec<&str>> = completion
+            .choices_by_prompt(2)
+            .into_iter()
+            .map(|choices| choices.iter().map(|choice| choice.text.as_str()).collect())
+            .collect();
+
+        assert_eq!(texts, [["a", "A"], ["B", "b"]]);
+        assert_eq!(
+            server.requests()[0].json()["prompt"],
+            json!(["Say a", "Say b"])
+        );
+        assert_eq!(
+            serde_json::to_value(Prompt::from(vec![vec![1, 2], vec![3]])).unwrap(),
+            json!([[1, 2], [3]])
+        );
+        assert_eq!(Prompt::from(vec![1, 2, 3]).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn completion_stream() {
+        let server = StubServer::start(vec![StubResponse::event_stream(&[
+            r#"{"id":"cmpl-1","object":"text_completion","created":1,"model":"text-davinci-003","choices":[{"text":"This","index":0,"logprobs":{"tokens":["This"],"token_logprobs":[-0.5],"top_logprobs":[{"This":-0.5}],"text_offset":[0]},"finish_reason":null}]}"#,
+            r#"{"id":"cmpl-1","object":"text_completion","created":1,"model":"text-davinci-003","choices":[{"text":"That","index":1,"logprobs":{"tokens":["That"],"token_logprobs":[-1.5],"top_logprobs":[{"That":-1.5}],"text_offset":[0]},"finish_reason":null}]}"#,
+            r#"{"id":"cmpl-1","object":"text_completion","created":1,"model":"text-davinci-003","choices":[{"text":" is","index":0,"logprobs":{"tokens":[" is"],"token_logprobs":[-0.25],"top_logprobs":[{" is":-0.25}],"text_offset":[4]},"finish_reason":"length"}]}"#,
+            r#"{"id":"cmpl-1","object":"text_completion","created":1,"model":"text-davinci-003","choices":[{"text":" was","index":1,"logprobs":{"tokens":[" was"],"token_logprobs":[-2.0],"top_logprobs":[{" was":-2.0}],"text_offset":[4]},"finish_reason":"length"}]}"#,
+            "[DONE]",
+        ])])
+        .await;
+        let client = OpenAiClient::new("key").base_url(server.url());
+        let stream = Completion::builder("text-davinci-003")
+            .prompt("Say this is a test")
+            .n(2u16)
+            .logprobs(1)
+            .client(&client)
+            .create_stream()
+            .await
+            .unwrap();
+        let completion = Completion::from_stream(stream).await.unwrap();
+
+        assert_eq!(completion.choices.len(), 2);
+        assert_eq!(completion.choices[0].text, "This is");
+        assert_eq!(completion.choices[1].text, "That was");
+        assert_eq!(
+            completion.choices[1].finish_reason.as_deref(),
+            Some("length")
+        );
+
+        let logprobs = completion.choices[0].logprobs.as_ref().unwrap();
+
+        assert_eq!(logprobs.tokens, ["This", " is"]);
+        assert_eq!(logprobs.token_logprobs, [Some(-0.5), Some(-0.25)]);
+        assert_eq!(logprobs.text_offset, [0, 4]);
+        assert_eq!(logprobs.top_logprobs[1].as_ref().unwrap()[" is"], -0.25);
+        assert_eq!(
+            completion.choices[1].logprobs.as_ref().unwrap().tokens,
+            ["That", " was"]
+        );
+        assert_eq!(logprobs.perplexity(), Some(0.375f64.exp()));
+        assert_eq!(logprobs.top_alternatives(1), [(" is", -0.25)]);
+        assert!(logprobs.top_alternatives(2).is_empty());
+
+        assert!(matches!(
+            Completion::from_stream(stream::empty()).await,
+            Err(Error::IncompleteResponse(_))
+        ));
+    }
 }
@@ -1,6 +1,9 @@
 //! Given a prompt and an instruction, the model will return an edited version of the prompt.
 
-use super::{openai_post, ApiResponseOrError, OpenAiError, Usage};
+use super::{
+    openai_post, request_client, validate::check_range, Error, OpenAiClient, Response, Usage,
+    Validate, ValidationError,
+};
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 
@@ -23,6 +26,7 @@ struct EditChoice {
 #[builder(pattern = "owned")]
 #[builder(name = "EditBuilder")]
 #[builder(setter(strip_option, into))]
+#[builder(build_fn(error = "Error"))]
 pub struct EditRequest {
     /// ID of the model to use.
     /// You can use the `text-davinci-edit-001` or `code-davinci-edit-001` model with this endpoint.
@@ -53,22 +57,46 @@ pub struct EditRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[builder(default)]
     pub top_p: Option<f32>,
+    /// The client to make the request with.
+    /// Defaults to the client configured with [`set_key`](crate::set_key).
+    #[serde(skip)]
+    #[builder(default)]
+    pub client: Option<OpenAiClient>,
+    /// The organization the request counts towards, instead of that of its client.
+    #[serde(skip)]
+    #[builder(default)]
+    pub organization: Option<String>,
+    /// The project the request counts towards, instead of that of its client.
+    #[serde(skip)]
+    #[builder(default)]
+    pub project: Option<String>,
+}
+
+impl Validate for EditRequest {
+    fn validate(&self) -> Result<(), ValidationError> {
+        check_range("temperature", self.temperature, 0.0, 2.0)?;
+        check_range("top_p", self.top_p, 0.0, 1.0)
+    }
 }
 
 impl Edit {
-    async fn create(request: &EditRequest) -> ApiResponseOrError<Self> {
-        let response: Result<Self, OpenAiError> = openai_post("edits", request).await?;
+    async fn create(request: &EditRequest) -> Result<Response<Self>, Error> {
+        request.validate()?;
 
-        match response {
-            Ok(mut edit) => {
-                for choice in &edit.choices_bad {
-                    edit.choices.push(choice.text.clone());
-                }
+        let client = request_client(
+            request.client.as_ref(),
+            request.organization.as_ref(),
+            request.project.as_ref(),
+        );
+        let response: Response<Self> = openai_post(client.as_ref(), "edits", request).await?;
 
-                Ok(Ok(edit))
+        Ok(response.map(|mut edit| {
+            for choice in &edit.choices_bad {
+                edit.choices.push(choice.text.clone());
             }
-            Err(_) => Ok(response),
-        }
+
+            edit
+        }))
     }
 
     pub fn builder(model: &str, instruction: impl Into<String>) -> EditBuilder {
@@ -79,16 +107,25 @@ impl Edit {
 }
 
 impl EditBuilder {
-    pub async fn create(self) -> ApiResponseOrError<Edit> {
-        Edit::create(&self.build().unwrap()).await
+    pub async fn create(self) -> Result<Edit, Error> {
+        Ok(self.create_with_metadata().await?.body)
+    }
+
+    /// Like [`EditBuilder::create`], but the metadata of the response is returned too.
+    pub async fn create_with_metadata(self) -> Result<Response<Edit>, Error> {
+        Edit::create(&self.build()?).await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::set_key;
+    use crate::{
+        set_key,
+        stub::{StubResponse, StubServer},
+    };
     use dotenvy::dotenv;
+    use serde_json::json;
     use std::env;
 
     #[tokio::test]
@@ -102,7 +139,6 @@ mod tests {
             .temperature(0.0)
             .create()
             .await
-            .unwrap()
             .unwrap();
 
         assert_eq!(
@@ -110,4 +146,35 @@ mod tests {
             "What day of the week is it?\n"
         );
     }
+
+    #[tokio::test]
+    async fn edit_stub() {
+        let server = StubServer::start(vec![StubResponse::json(
+            200,
+            json!({
+                "object": "edit",
+                "created": 1589478378,
+                "choices": [{"text": "What day of the week is it?\n", "index": 0}],
+                "usage": {"prompt_tokens": 25, "completion_tokens": 32, "total_tokens": 57},
+            }),
+        )])
+        .await;
+        let client = OpenAiClient::new("key").base_url(server.url());
+        let edit = Edit::builder("text-davinci-edit-001", "Fix the spelling mistakes")
+            .input("What day of the wek is it?")
+            .client(&client)
+            .create()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            edit.choices.first().unwrap(),
+            "What day of the week is it?\n"
+        );
+
+        let request = &server.requests()[0];
+
+        assert_eq!(request.path, "/v1/edits");
+        assert_eq!(request.json()["instruction"], "Fix the spelling mistakes");
+    }
 }
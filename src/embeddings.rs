@@ -2,37 +2,214 @@
 //!
 //! Related guide: [Embeddings](https://beta.openai.com/docs/guides/embeddings)
 
-use super::{openai_post, ApiResponseOrError};
-use serde::{Deserialize, Serialize};
+use super::{
+    openai_post, request_client, validate::check_range, Error, OpenAiClient, Response, Validate,
+    ValidationError,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use derive_builder::Builder;
+use serde::{de, Deserialize, Deserializer, Serialize};
 
-#[derive(Serialize, Clone)]
-struct CreateEmbeddingsRequestBody<'a> {
-    model: &'a str,
-    input: Vec<&'a str>,
-    #[serde(skip_serializing_if = "str::is_empty")]
-    user: &'a str,
-}
+mod batch;
+mod index;
+mod metric;
+
+pub use batch::{Batch, BatchEmbeddings, ChunkEmbedding};
+pub use index::{Hnsw, Index, Quantization, SearchResult};
+pub use metric::Metric;
+
+/// The most inputs that the API accepts in one request.
+const MAX_INPUTS: usize = 2048;
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Embeddings {
+    /// The embeddings in the order of the inputs.
+    #[serde(deserialize_with = "deserialize_data")]
     pub data: Vec<Embedding>,
     pub model: String,
     pub usage: EmbeddingsUsage,
 }
 
-#[derive(Deserialize, Clone, Copy)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
 pub struct EmbeddingsUsage {
     pub prompt_tokens: u32,
     pub total_tokens: u32,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Embedding {
-    #[serde(rename = "embedding")]
+    /// The vector, which is decoded from base64 if it was requested with [`EncodingFormat::Base64`].
+    #[serde(rename = "embedding", deserialize_with = "deserialize_vector")]
     pub vec: Vec<f64>,
 }
 
+/// The input(s) of an [`EmbeddingsRequest`].
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Input {
+    Text(String),
+    Texts(Vec<String>),
+    /// An input given as token IDs, in the tokenizer of the model.
+    Tokens(Vec<u32>),
+    /// Several inputs given as token IDs, in the tokenizer of the model.
+    TokenArrays(Vec<Vec<u32>>),
+}
+
+impl Input {
+    /// Returns the number of inputs, which is the number of embeddings returned.
+    pub fn len(&self) -> usize {
+        match self {
+            Input::Text(_) | Input::Tokens(_) => 1,
+            Input::Texts(texts) => texts.len(),
+            Input::TokenArrays(token_arrays) => token_arrays.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl From<String> for Input {
+    fn from(text: String) -> Self {
+        Input::Text(text)
+    }
+}
+
+impl From<&str> for Input {
+    fn from(text: &str) -> Self {
+        Input::Text(text.to_string())
+    }
+}
+
+impl From<&String> for Input {
+    fn from(text: &String) -> Self {
+        Input::Text(text.clone())
+    }
+}
+
+impl From<Vec<String>> for Input {
+    fn from(texts: Vec<String>) -> Self {
+        Input::Texts(texts)
+    }
+}
+
+impl From<Vec<&str>> for Input {
+    fn from(texts: Vec<&str>) -> Self {
+        Input::Texts(texts.into_iter().map(str::to_string).collect())
+    }
+}
+
+impl<const N: usize> From<[&str; N]> for Input {
+    fn from(texts: [&str; N]) -> Self {
+        Input::Texts(texts.into_iter().map(str::to_string).collect())
+    }
+}
+
+impl From<Vec<u32>> for Input {
+    fn from(tokens: Vec<u32>) -> Self {
+        Input::Tokens(tokens)
+    }
+}
+
+impl From<&[u32]> for Input {
+    fn from(tokens: &[u32]) -> Self {
+        Input::Tokens(tokens.to_vec())
+    }
+}
+
+impl From<Vec<Vec<u32>>> for Input {
+    fn from(token_arrays: Vec<Vec<u32>>) -> Self {
+        Input::TokenArrays(token_arrays)
+    }
+}
+
+/// How the API sends the vectors of embeddings, see [`EmbeddingsBuilder::encoding_format`].
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodingFormat {
+    /// Vectors are sent as arrays of numbers.
+    #[default]
+    Float,
+    /// Vectors are sent as base64 strings of little-endian `f32`s, which are about 4 times smaller.
+    /// They are decoded into [`Embedding::vec`] like other vectors.
+    Base64,
+}
+
+#[derive(Serialize, Builder, Debug, Clone)]
+#[builder(pattern = "owned")]
+#[builder(name = "EmbeddingsBuilder")]
+#[builder(setter(strip_option, into))]
+#[builder(build_fn(error = "Error"))]
+pub struct EmbeddingsRequest {
+    /// ID of the model to use.
+    /// You can use the [List models](https://beta.openai.com/docs/api-reference/models/list)
+    /// API to see all of your available models, or see our [Model overview](https://beta.openai.com/docs/models/overview)
+    /// for descriptions of them.
+    model: String,
+    /// Input text to get embeddings for, encoded as a string or array of tokens.
+    /// To get embeddings for up to 2048 inputs in a single request, pass an array of strings or array of token arrays.
+    /// Each input must not exceed 8192 tokens in length.
+    input: Input,
+    /// How the vectors are sent. Defaults to [`EncodingFormat::Float`].
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding_format: Option<EncodingFormat>,
+    /// The number of dimensions of the vectors, which shortens them.
+    /// Only supported by `text-embedding-3` and later models.
+    #[builder(setter(into = false), default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dimensions: Option<u32>,
+    /// A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse.
+    /// [Learn more](https://beta.openai.com/docs/guides/safety-best-practices/end-user-ids).
+    #[builder(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    user: String,
+    /// The client to make the request with.
+    /// Defaults to the client configured with [`set_key`](crate::set_key).
+    #[builder(default)]
+    #[serde(skip)]
+    client: Option<OpenAiClient>,
+    /// The organization the request counts towards, instead of that of its client.
+    #[builder(default)]
+    #[serde(skip)]
+    organization: Option<String>,
+    /// The project the request counts towards, instead of that of its client.
+    #[builder(default)]
+    #[serde(skip)]
+    project: Option<String>,
+}
+
+impl Validate for EmbeddingsRequest {
+    fn validate(&self) -> Result<(), ValidationError> {
+        let empty = match &self.input {
+            Input::Text(text) => text.is_empty(),
+            Input::Texts(texts) => texts.is_empty() || texts.iter().any(String::is_empty),
+            Input::Tokens(tokens) => tokens.is_empty(),
+            Input::TokenArrays(token_arrays) => {
+                token_arrays.is_empty() || token_arrays.iter().any(Vec::is_empty)
+            }
+        };
+
+        if empty {
+            return Err(ValidationError::Empty { parameter: "input" });
+        }
+
+        check_range(
+            "input",
+            Some(self.input.len() as f64),
+            1.0,
+            MAX_INPUTS as f64,
+        )?;
+        check_range("dimensions", self.dimensions, 1.0, 3072.0)
+    }
+}
+
 impl Embeddings {
+    pub fn builder(model: &str, input: impl Into<Input>) -> EmbeddingsBuilder {
+        EmbeddingsBuilder::create_empty().model(model).input(input)
+    }
+
     /// Creates an embedding vector representing the input text.
     ///
     /// # Arguments
@@ -46,14 +223,62 @@ impl Embeddings {
     ///   Each input must not exceed 8192 tokens in length.
     /// * `user` - A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse.
     ///   [Learn more](https://beta.openai.com/docs/guides/safety-best-practices/end-user-ids).
-    pub async fn create(model: &str, input: Vec<&str>, user: &str) -> ApiResponseOrError<Self> {
-        openai_post(
-            "embeddings",
-            &CreateEmbeddingsRequestBody { model, input, user },
-        )
-        .await
+    ///
+    /// The input is sent in a single request; use a [`Batch`] to embed more inputs, or longer ones, than a request allows.
+    /// Use [`Embeddings::builder`] to set other parameters, or to pass tokens.
+    pub async fn create(model: &str, input: Vec<&str>, user: &str) -> Result<Self, Error> {
+        Self::request(None, model, input.into(), user).await
+    }
+
+    /// Like [`Embeddings::create`], but the request is made with `client`.
+    pub async fn create_with_client(
+        model: &str,
+        input: Vec<&str>,
+        user: &str,
+        client: &OpenAiClient,
+    ) -> Result<Self, Error> {
+        Self::request(Some(client), model, input.into(), user).await
+    }
+
+    /// Like [`Embeddings::create`], but the request is configured with [`Embeddings::builder`]
+    /// and the metadata of the response is returned too.
+    pub async fn create_with_metadata(
+        request: &EmbeddingsRequest,
+    ) -> Result<Response<Self>, Error> {
+        request.validate()?;
+
+        let client = request_client(
+            request.client.as_ref(),
+            request.organization.as_ref(),
+            request.project.as_ref(),
+        );
+
+        openai_post(client.as_ref(), "embeddings", request).await
+    }
+
+    async fn request(
+        client: Option<&OpenAiClient>,
+        model: &str,
+        input: Input,
+        user: &str,
+    ) -> Result<Self, Error> {
+        let request = EmbeddingsRequest {
+            model: model.to_string(),
+            input,
+            encoding_format: None,
+            dimensions: None,
+            user: user.to_string(),
+            client: client.cloned(),
+            organization: None,
+            project: None,
+        };
+
+        Self::create_with_metadata(&request)
+            .await
+            .map(Response::into_body)
     }
 
+    /// Returns the cosine similarity of every embedding with the next one, see [`Embedding::distance`].
     pub fn distances(&self) -> Vec<f64> {
         let mut distances = Vec::new();
         let mut last_embedding: Option<&Embedding> = None;
@@ -70,34 +295,122 @@ impl Embeddings {
     }
 }
 
+impl EmbeddingsBuilder {
+    pub async fn create(self) -> Result<Embeddings, Error> {
+        Ok(self.create_with_metadata().await?.body)
+    }
+
+    /// Like [`EmbeddingsBuilder::create`], but the metadata of the response is returned too.
+    pub async fn create_with_metadata(self) -> Result<Response<Embeddings>, Error> {
+        Embeddings::create_with_metadata(&self.build()?).await
+    }
+}
+
 impl Embedding {
-    pub async fn create(model: &str, input: &str, user: &str) -> ApiResponseOrError<Self> {
-        let response = Embeddings::create(model, vec![input], user).await?;
+    pub async fn create(model: &str, input: &str, user: &str) -> Result<Self, Error> {
+        Self::request(None, model, input, user).await
+    }
 
-        match response {
-            Ok(mut embeddings) => Ok(Ok(embeddings.data.swap_remove(0))),
-            Err(error) => Ok(Err(error)),
-        }
+    /// Like [`Embedding::create`], but the request is made with `client`.
+    pub async fn create_with_client(
+        model: &str,
+        input: &str,
+        user: &str,
+        client: &OpenAiClient,
+    ) -> Result<Self, Error> {
+        Self::request(Some(client), model, input, user).await
+    }
+
+    async fn request(
+        client: Option<&OpenAiClient>,
+        model: &str,
+        input: &str,
+        user: &str,
+    ) -> Result<Self, Error> {
+        let embeddings = Embeddings::request(client, model, input.into(), user).await?;
+
+        embeddings
+            .data
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::IncompleteResponse("expected 1 embedding, but got 0".to_string()))
     }
 
+    /// Returns the cosine similarity of this embedding and `other`, or NaN if they have different dimensions.
+    ///
+    /// Use [`Embedding::score`] to choose the metric and handle embeddings with different dimensions.
     pub fn distance(&self, other: &Self) -> f64 {
-        let dot_product: f64 = self
-            .vec
-            .iter()
-            .zip(other.vec.iter())
-            .map(|(x, y)| x * y)
-            .sum();
-        let product_of_lengths = (self.vec.len() * other.vec.len()) as f64;
+        self.score(other, Metric::Cosine).unwrap_or(f64::NAN)
+    }
 
-        dot_product / product_of_lengths
+    /// Compares this embedding with `other` using `metric`,
+    /// failing with [`Error::DimensionMismatch`] if they have different dimensions.
+    pub fn score(&self, other: &Self, metric: Metric) -> Result<f64, Error> {
+        metric.compute(&self.vec, &other.vec)
+    }
+}
+
+/// Reads the embeddings of a response and sorts them by the `index` of their input.
+fn deserialize_data<'de, D>(deserializer: D) -> Result<Vec<Embedding>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Indexed {
+        #[serde(default)]
+        index: usize,
+        #[serde(flatten)]
+        embedding: Embedding,
+    }
+
+    let mut data = Vec::<Indexed>::deserialize(deserializer)?;
+
+    data.sort_by_key(|indexed| indexed.index);
+
+    Ok(data.into_iter().map(|indexed| indexed.embedding).collect())
+}
+
+/// Reads a vector sent as an array of numbers, or as a base64 string of little-endian `f32`s.
+fn deserialize_vector<'de, D>(deserializer: D) -> Result<Vec<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Vector {
+        Float(Vec<f64>),
+        Base64(String),
+    }
+
+    match Vector::deserialize(deserializer)? {
+        Vector::Float(vec) => Ok(vec),
+        Vector::Base64(base64) => {
+            let bytes = STANDARD.decode(base64).map_err(de::Error::custom)?;
+
+            if bytes.len() % 4 != 0 {
+                return Err(de::Error::invalid_length(
+                    bytes.len(),
+                    &"a multiple of 4 bytes",
+                ));
+            }
+
+            Ok(bytes
+                .chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]).into())
+                .collect())
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::set_key;
+    use crate::{
+        set_key,
+        stub::{StubResponse, StubServer},
+    };
     use dotenvy::dotenv;
+    use serde_json::json;
     use std::env;
 
     #[tokio::test]
@@ -111,7 +424,6 @@ mod tests {
             "",
         )
         .await
-        .unwrap()
         .unwrap();
 
         assert!(!embeddings.data.first().unwrap().vec.is_empty());
@@ -128,12 +440,148 @@ mod tests {
             "",
         )
         .await
-        .unwrap()
         .unwrap();
 
         assert!(!embedding.vec.is_empty());
     }
 
+    #[tokio::test]
+    async fn embeddings_stub() {
+        let server = StubServer::start(vec![StubResponse::json(
+            200,
+            json!({
+                "object": "list",
+                "data": [
+                    {"object": "embedding", "embedding": [0.1, 0.2], "index": 0},
+                    {"object": "embedding", "embedding": [0.3, 0.4], "index": 1},
+                ],
+                "model": "text-embedding-ada-002",
+                "usage": {"prompt_tokens": 8, "total_tokens": 8},
+            }),
+        )])
+        .await;
+        let client = OpenAiClient::new("key").base_url(server.url());
+        let embeddings = Embeddings::create_with_client(
+            "text-embedding-ada-002",
+            vec!["The food was delicious", "and the waiter..."],
+            "",
+            &client,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(embeddings.data[1].vec, [0.3, 0.4]);
+
+        let request = &server.requests()[0];
+
+        assert_eq!(request.path, "/v1/embeddings");
+        assert_eq!(
+            request.json(),
+            json!({
+                "model": "text-embedding-ada-002",
+                "input": ["The food was delicious", "and the waiter..."],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn embeddings_builder() {
+        let vector: Vec<u8> = [0.5f32, -2.0]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        let server = StubServer::start(vec![StubResponse::json(
+            200,
+            json!({
+                "object": "list",
+                "data": [
+                    {"object": "embedding", "embedding": STANDARD.encode(vector), "index": 0},
+                ],
+                "model": "text-embedding-3-small",
+                "usage": {"prompt_tokens": 3, "total_tokens": 3},
+            }),
+        )])
+        .await;
+        let client = OpenAiClient::new("key").base_url(server.url());
+        let embeddings = Embeddings::builder("text-embedding-3-small", vec![vec![9906, 1917, 0]])
+            .encoding_format(EncodingFormat::Base64)
+            .dimensions(2)
+            .client(&client)
+            .create()
+            .await
+            .unwrap();
+
+        assert_eq!(embeddings.data[0].vec, [0.5, -2.0]);
+        assert_eq!(
+            server.requests()[0].json(),
+            json!({
+                "model": "text-embedding-3-small",
+                "input": [[9906, 1917, 0]],
+                "encoding_format": "base64",
+                "dimensions": 2,
+            })
+        );
+
+        for builder in [
+            Embeddings::builder("text-embedding-3-small", ["text", ""]),
+            Embeddings::builder("text-embedding-3-small", Vec::<String>::new()),
+            Embeddings::builder("text-embedding-3-small", "text").dimensions(0),
+        ] {
+            assert!(matches!(
+                builder.client(&client).create().await,
+                Err(Error::Validation(_))
+            ));
+        }
+
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn invalid_base64() {
+        let result = serde_json::from_value::<Embedding>(json!({"embedding": "AAAAAP8="}));
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn no_embedding() {
+        let server = StubServer::start(vec![StubResponse::json(
+            200,
+            json!({
+                "object": "list",
+                "data": [],
+                "model": "text-embedding-ada-002",
+                "usage": {"prompt_tokens": 1, "total_tokens": 1},
+            }),
+        )])
+        .await;
+        let client = OpenAiClient::new("key").base_url(server.url());
+        let error = Embedding::create_with_client("text-embedding-ada-002", "text", "", &client)
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "incomplete response: expected 1 embedding, but got 0"
+        );
+    }
+
+    #[test]
+    fn sorted_by_index() {
+        let embeddings = serde_json::from_value::<Embeddings>(json!({
+            "data": [
+                {"object": "embedding", "embedding": [1.0], "index": 1},
+                {"object": "embedding", "embedding": [0.0], "index": 0},
+            ],
+            "model": "text-embedding-ada-002",
+            "usage": {"prompt_tokens": 2, "total_tokens": 2},
+        }))
+        .unwrap();
+
+        assert_eq!(embeddings.data[0].vec, [0.0]);
+        assert_eq!(embeddings.data[1].vec, [1.0]);
+    }
+
     #[test]
     fn right_angle() {
         let embeddings = Embeddings {
@@ -174,5 +622,25 @@ mod tests {
         };
 
         assert_ne!(embeddings.distances()[0], 0.0);
+        assert!((embeddings.distances()[0] - 0.5f64.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn mismatched_dimensions() {
+        let a = Embedding {
+            vec: vec![1.0, 0.0],
+        };
+        let b = Embedding {
+            vec: vec![1.0, 0.0, 0.0],
+        };
+
+        assert!(a.distance(&b).is_nan());
+        assert!(matches!(
+            a.score(&b, Metric::Euclidean),
+            Err(Error::DimensionMismatch {
+                expected: 2,
+                actual: 3
+            })
+        ));
     }
 }
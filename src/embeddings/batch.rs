@@ -0,0 +1,372 @@
+use super::{Embedding, Embeddings, EmbeddingsUsage, MAX_INPUTS};
+use crate::{Error, OpenAiClient, ValidationError};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use std::ops::Range;
+
+/// Embeds any number of texts, splitting them into as many requests as needed.
+///
+/// Inputs are sent in groups of at most 2048, with up to 4 requests in flight at once.
+/// With [`Batch::chunking`], texts that are too long for the model are split into overlapping chunks,
+/// which are embedded separately.
+/// The embeddings are returned in the order of the inputs, each with the input and the part of it that it stands for.
+///
+/// ## Examples
+///
+/// ```rust,no_run
+/// use openai::embeddings::Batch;
+///
+/// # async fn run(documents: Vec<String>) -> Result<(), openai::Error> {
+/// let embeddings = Batch::new("text-embedding-ada-002")
+///     .chunking(8000, 200)
+///     .concurrency(8)
+///     .create(&documents)
+///     .await?;
+///
+/// for chunk in &embeddings.data {
+///     println!("{:?} of document {}", chunk.range, chunk.input);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Batch {
+    model: String,
+    user: String,
+    inputs_per_request: usize,
+    chunking: Option<(usize, usize)>,
+    concurrency: usize,
+    client: Option<OpenAiClient>,
+}
+
+/// The embeddings of a [`Batch`].
+#[derive(Debug, Clone)]
+pub struct BatchEmbeddings {
+    /// The embeddings of the chunks, ordered by input, then by chunk.
+    pub data: Vec<ChunkEmbedding>,
+    pub model: String,
+    /// The tokens used by all the requests together.
+    pub usage: EmbeddingsUsage,
+}
+
+/// The embedding of a part of an input of a [`Batch`].
+#[derive(Debug, Clone)]
+pub struct ChunkEmbedding {
+    /// The position of the input in the batch.
+    pub input: usize,
+    /// The position of the chunk in the input, 0 unless the input was split.
+    pub chunk: usize,
+    /// The bytes of the input that were embedded, which is the whole input unless it was split.
+    pub range: Range<usize>,
+    pub embedding: Embedding,
+}
+
+impl Batch {
+    /// Starts a batch for `model`, which sends the inputs as they are.
+    pub fn new(model: impl Into<String>) -> Self {
+        Batch {
+            model: model.into(),
+            user: String::new(),
+            inputs_per_request: MAX_INPUTS,
+            chunking: None,
+            concurrency: 4,
+            client: None,
+        }
+    }
+
+    /// A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse.
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.user = user.into();
+        self
+    }
+
+    /// Sets how many inputs, or chunks of inputs, are sent in one request, from 1 to 2048.
+    pub fn inputs_per_request(mut self, inputs_per_request: usize) -> Self {
+        self.inputs_per_request = inputs_per_request.clamp(1, MAX_INPUTS);
+        self
+    }
+
+    /// Splits inputs longer than `max_tokens` into chunks of `max_tokens`,
+    /// each repeating the last `overlap` tokens of the one before.
+    ///
+    /// Tokens are counted exactly with the `tokenizer` feature, and estimated as 4 bytes each otherwise.
+    /// Chunks are cut between tokens of the whole input, and a chunk may count a few more tokens on its own,
+    /// so `max_tokens` should leave some margin below the limit of the model, which is 8191 tokens.
+    pub fn chunking(mut self, max_tokens: usize, overlap: usize) -> Self {
+        self.chunking = Some((max_tokens, overlap));
+        self
+    }
+
+    /// Sets how many requests may be in flight at once, at least 1.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// The client to make requests with.
+    /// Defaults to the client configured with [`set_key`](crate::set_key).
+    pub fn client(mut self, client: impl Into<OpenAiClient>) -> Self {
+        self.client = Some(client.into());
+        self
+    }
+
+    /// Embeds `inputs`, failing with the first error if any request fails.
+    ///
+    /// Empty inputs, which the API rejects, are skipped and have no embeddings.
+    pub async fn create(&self, inputs: &[impl AsRef<str>]) -> Result<BatchEmbeddings, Error> {
+        let mut chunks = Vec::new();
+
+        for (input, text) in inputs.iter().map(AsRef::as_ref).enumerate() {
+            if text.is_empty() {
+                continue;
+            }
+
+            let ranges = match self.chunking {
+                Some((max_tokens, overlap)) => {
+                    if overlap >= max_tokens {
+                        return Err(ValidationError::OutOfRange {
+                            parameter: "overlap",
+                            value: overlap as f64,
+                            min: 0.0,
+                            max: max_tokens.saturating_sub(1) as f64,
+                        }
+                        .into());
+                    }
+
+                    chunk(text, &token_ends(&self.model, text), max_tokens, overlap)
+                }
+                None => vec![Range {
+                    start: 0,
+                    end: text.len(),
+                }],
+            };
+
+            chunks.extend(
+                ranges
+                    .into_iter()
+                    .enumerate()
+                    .map(|(chunk, range)| (input, chunk, range, text)),
+            );
+        }
+
+        let responses: Vec<Embeddings> = stream::iter(chunks.chunks(self.inputs_per_request))
+            .map(|group| {
+                let input = group
+                    .iter()
+                    .map(|(_, _, range, text)| text[range.clone()].to_string())
+                    .collect::<Vec<_>>();
+
+                async move {
+                    let response = Embeddings::request(
+                        self.client.as_ref(),
+                        &self.model,
+                        input.into(),
+                        &self.user,
+                    )
+                    .await?;
+
+                    if response.data.len() != group.len() {
+                        return Err(Error::IncompleteResponse(format!(
+                            "expected {} embeddings, but got {}",
+                            group.len(),
+                            response.data.len()
+                        )));
+                    }
+
+                    Ok(response)
+                }
+            })
+            .buffered(self.concurrency)
+            .try_collect()
+            .await?;
+
+        let mut usage = EmbeddingsUsage {
+            prompt_tokens: 0,
+            total_tokens: 0,
+        };
+        let model = responses
+            .first()
+            .map_or_else(|| self.model.clone(), |response| response.model.clone());
+
+        for response in &responses {
+            usage.prompt_tokens += response.usage.prompt_tokens;
+            usage.total_tokens += response.usage.total_tokens;
+        }
+
+        let embeddings = responses.into_iter().flat_map(|response| response.data);
+
+        Ok(BatchEmbeddings {
+            data: chunks
+                .into_iter()
+                .zip(embeddings)
+                .map(|((input, chunk, range, _), embedding)| ChunkEmbedding {
+                    input,
+                    chunk,
+                    range,
+                    embedding,
+                })
+                .collect(),
+            model,
+            usage,
+        })
+    }
+}
+
+/// Returns the byte offsets of the ends of the tokens of `text`.
+#[cfg(feature = "tokenizer")]
+fn token_ends(model: &str, text: &str) -> Vec<usize> {
+    use crate::tokenizer::Encoding;
+
+    let encoding = Encoding::for_model(model).unwrap_or(Encoding::Cl100kBase);
+    let mut end = 0;
+
+    encoding
+        .encode(text)
+        .into_iter()
+        .map(|token| {
+            end += encoding.decode_bytes(&[token]).len();
+            end
+        })
+        .collect()
+}
+
+/// Returns the byte offsets of the ends of the tokens of `text`, estimated as 4 bytes each.
+#[cfg(not(feature = "tokenizer"))]
+fn token_ends(_model: &str, text: &str) -> Vec<usize> {
+    (1..=text.len().div_ceil(4))
+        .map(|token| (token * 4).min(text.len()))
+        .collect()
+}
+
+/// Splits `text`, made of tokens ending at `ends`, into ranges of at most `max_tokens` tokens
+/// that repeat the last `overlap` tokens of the range before.
+///
+/// Ranges are moved back to the start of a character when a token ends in the middle of one.
+fn chunk(text: &str, ends: &[usize], max_tokens: usize, overlap: usize) -> Vec<Range<usize>> {
+    let boundary = |mut offset: usize| {
+        while !text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        offset
+    };
+    let mut ranges = Vec::new();
+    let mut first = 0;
+
+    loop {
+        let last = (first + max_tokens).min(ends.len());
+        let start = first.checked_sub(1).map_or(0, |token| ends[token]);
+        let end = last.checked_sub(1).map_or(0, |token| ends[token]);
+
+        ranges.push(boundary(start)..boundary(end));
+
+        if last == ends.len() {
+            return ranges;
+        }
+
+        first += max_tokens - overlap;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub::{StubResponse, StubServer};
+    use serde_json::json;
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn chunks() {
+        let text = "aaaabbbbccccdd";
+        let ends = [4, 8, 12, 14];
+
+        assert_eq!(chunk(text, &ends, 4, 0), [0..14]);
+        assert_eq!(chunk("", &[], 4, 0), [0..0]);
+        assert_eq!(chunk(text, &ends, 2, 0), [0..8, 8..14]);
+        assert_eq!(chunk(text, &ends, 3, 1), [0..12, 8..14]);
+        assert_eq!(chunk(text, &ends, 2, 1), [0..8, 4..12, 8..14]);
+
+        // "é" is 2 bytes, split between the first and second token
+        assert_eq!(chunk("aéb", &[2, 4], 1, 0), [0..1, 1..4]);
+    }
+
+    #[tokio::test]
+    async fn batches() {
+        let response = |values: &[f64], tokens: u32| {
+            StubResponse::json(
+                200,
+                json!({
+                    "object": "list",
+                    "data": values
+                        .iter()
+                        .enumerate()
+                        .rev()
+                        .map(|(index, value)| json!({"object": "embedding", "embedding": [value], "index": index}))
+                        .collect::<Vec<_>>(),
+                    "model": "text-embedding-ada-002",
+                    "usage": {"prompt_tokens": tokens, "total_tokens": tokens},
+                }),
+            )
+        };
+        let server = StubServer::start(vec![
+            response(&[0.0, 1.0], 4),
+            response(&[2.0, 3.0], 4),
+            response(&[4.0], 1),
+            response(&[5.0], 1),
+        ])
+        .await;
+        let client = OpenAiClient::new("key").base_url(server.url());
+        // 17 tokens, whether they are counted or estimated
+        let text = format!("the{}", " the".repeat(16));
+        let embeddings = Batch::new("text-embedding-ada-002")
+            .chunking(8, 2)
+            .inputs_per_request(2)
+            .concurrency(1)
+            .client(&client)
+            .create(&["first", "", text.as_str(), "last"])
+            .await
+            .unwrap();
+        let provenance: Vec<_> = embeddings
+            .data
+            .iter()
+            .map(|chunk| (chunk.input, chunk.chunk, chunk.embedding.vec[0]))
+            .collect();
+
+        assert_eq!(
+            provenance,
+            [
+                (0, 0, 0.0),
+                (2, 0, 1.0),
+                (2, 1, 2.0),
+                (2, 2, 3.0),
+                (3, 0, 4.0)
+            ]
+        );
+        assert_eq!(embeddings.data[4].range, 0..4);
+        assert_eq!(embeddings.usage.total_tokens, 9);
+
+        let requests = server.requests();
+
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].json()["input"][0], "first");
+        assert_eq!(requests[2].json()["input"], json!(["last"]));
+        assert_eq!(
+            Batch::new("text-embedding-ada-002")
+                .chunking(8, 8)
+                .client(&client)
+                .create(&["text"])
+                .await
+                .unwrap_err()
+                .to_string(),
+            "invalid request: `overlap` is 8, but must be from 0 to 7"
+        );
+        assert_eq!(
+            Batch::new("text-embedding-ada-002")
+                .client(&client)
+                .create(&["first", "second"])
+                .await
+                .unwrap_err()
+                .to_string(),
+            "incomplete response: expected 2 embeddings, but got 1"
+        );
+    }
+}
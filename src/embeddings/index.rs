@@ -0,0 +1,681 @@
+use super::{Embedding, Metric};
+use crate::Error;
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+mod file;
+
+pub use file::Quantization;
+
+/// Embeddings with a payload each, like the text they were created from, which can be searched for the closest ones to a query.
+///
+/// By default, every embedding is compared with the query. For larger indexes,
+/// [`Index::with_hnsw`] finds approximate results much faster with a [`Hnsw`] graph.
+///
+/// Vectors are stored next to each other as `f32`, the precision of the API.
+/// Indexes can be saved to files with [`Index::save`] and loaded back with [`Index::load`].
+///
+/// ## Examples
+///
+/// ```rust
+/// use openai::embeddings::{Embedding, Index, Metric};
+///
+/// let mut index = Index::new();
+///
+/// index.add(&Embedding { vec: vec![1.0, 0.0] }, "east")?;
+/// index.add(&Embedding { vec: vec![0.0, 1.0] }, "north")?;
+///
+/// let results = index.search(&Embedding { vec: vec![0.8, 0.6] }, 1, Metric::Cosine)?;
+///
+/// assert_eq!(*results[0].payload, "east");
+/// # Ok::<(), openai::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Index<P> {
+    dimensions: usize,
+    /// The vectors of every slot, one after the other.
+    vectors: Vec<f32>,
+    /// The entries of every slot, `None` once removed.
+    entries: Vec<Option<Entry<P>>>,
+    /// The slot of every ID.
+    slots: HashMap<u64, usize>,
+    next_id: u64,
+    graph: Option<Graph>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry<P> {
+    id: u64,
+    payload: P,
+}
+
+/// The parameters of a [hierarchical navigable small world](https://arxiv.org/abs/1603.09320) graph,
+/// which an [`Index`] can search instead of comparing the query with every embedding.
+///
+/// The graph is built for one metric; searches with another metric compare every embedding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hnsw {
+    pub metric: Metric,
+    /// The number of neighbors of every embedding, twice as many on the lowest layer.
+    /// More neighbors give better results, but take more memory and time to build.
+    pub m: usize,
+    /// The number of candidates considered when adding an embedding.
+    pub ef_construction: usize,
+    /// The number of candidates considered when searching, at least the number of results.
+    pub ef_search: usize,
+}
+
+/// An embedding found by [`Index::search`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchResult<'a, P> {
+    pub id: u64,
+    /// The score of the embedding with the metric of the search.
+    pub score: f64,
+    pub payload: &'a P,
+}
+
+impl<P> Index<P> {
+    pub fn new() -> Self {
+        Index {
+            dimensions: 0,
+            vectors: Vec::new(),
+            entries: Vec::new(),
+            slots: HashMap::new(),
+            next_id: 0,
+            graph: None,
+        }
+    }
+
+    /// Creates an index that searches approximately, with a graph built with `hnsw` as embeddings are added.
+    pub fn with_hnsw(hnsw: Hnsw) -> Self {
+        Index {
+            graph: Some(Graph::new(hnsw)),
+            ..Self::new()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Returns the dimensions of the embeddings, or `None` if nothing was added yet.
+    pub fn dimensions(&self) -> Option<usize> {
+        (!self.entries.is_empty()).then_some(self.dimensions)
+    }
+
+    /// Adds an embedding with its payload and returns its ID.
+    ///
+    /// Fails with [`Error::DimensionMismatch`] if the embedding doesn't have the dimensions of the others.
+    pub fn add(&mut self, embedding: &Embedding, payload: P) -> Result<u64, Error> {
+        if self.entries.is_empty() {
+            self.dimensions = embedding.vec.len();
+        } else if embedding.vec.len() != self.dimensions {
+            return Err(Error::DimensionMismatch {
+                expected: self.dimensions,
+                actual: embedding.vec.len(),
+            });
+        }
+
+        let id = self.next_id;
+
+        self.next_id += 1;
+        self.insert(id, embedding.vec.iter().map(|x| *x as f32), payload);
+
+        Ok(id)
+    }
+
+    pub fn get(&self, id: u64) -> Option<&P> {
+        let slot = *self.slots.get(&id)?;
+
+        self.entries[slot].as_ref().map(|entry| &entry.payload)
+    }
+
+    /// Removes an embedding and returns its payload, or `None` if there is no embedding with this ID.
+    pub fn remove(&mut self, id: u64) -> Option<P> {
+        let slot = self.slots.remove(&id)?;
+        let entry = self.entries[slot].take()?;
+
+        // Removed embeddings stay in the graph to keep it connected, until they outnumber the others
+        if self.entries.len() > 2 * self.slots.len() {
+            self.compact();
+        }
+
+        Some(entry.payload)
+    }
+
+    /// Returns the `k` embeddings closest to `query` with `metric`, from closest to farthest.
+    ///
+    /// Fails with [`Error::DimensionMismatch`] if the query doesn't have the dimensions of the embeddings.
+    pub fn search(
+        &self,
+        query: &Embedding,
+        k: usize,
+        metric: Metric,
+    ) -> Result<Vec<SearchResult<'_, P>>, Error> {
+        if self.entries.is_empty() || k == 0 {
+            return Ok(Vec::new());
+        }
+
+        if query.vec.len() != self.dimensions {
+            return Err(Error::DimensionMismatch {
+                expected: self.dimensions,
+                actual: query.vec.len(),
+            });
+        }
+
+        let query: Vec<f32> = query.vec.iter().map(|x| *x as f32).collect();
+        let vectors = self.vectors();
+        let mut found = match &self.graph {
+            Some(graph) if graph.params.metric == metric => {
+                let removed = self.entries.len() - self.slots.len();
+
+                graph
+                    .search(&query, k + removed, vectors)
+                    .into_iter()
+                    .filter(|scored| self.entries[scored.slot].is_some())
+                    .take(k)
+                    .collect()
+            }
+            _ => {
+                let mut found: Vec<Scored> = (0..self.entries.len())
+                    .filter(|slot| self.entries[*slot].is_some())
+                    .map(|slot| Scored::new(metric, &query, vectors.get(slot), slot))
+                    .collect();
+
+                if found.len() > k {
+                    found.select_nth_unstable(k);
+                    found.truncate(k);
+                }
+
+                found.sort_unstable();
+                found
+            }
+        };
+
+        found.truncate(k);
+
+        Ok(found
+            .into_iter()
+            .map(|scored| {
+                let entry = self.entries[scored.slot].as_ref().unwrap();
+
+                SearchResult {
+                    id: entry.id,
+                    score: scored.score(metric),
+                    payload: &entry.payload,
+                }
+            })
+            .collect())
+    }
+
+    /// Adds an embedding with the given ID, which must have the dimensions of the index.
+    fn insert(&mut self, id: u64, vector: impl IntoIterator<Item = f32>, payload: P) {
+        let slot = self.entries.len();
+
+        self.vectors.extend(vector);
+        self.entries.push(Some(Entry { id, payload }));
+        self.slots.insert(id, slot);
+        self.next_id = self.next_id.max(id + 1);
+
+        if let Some(graph) = &mut self.graph {
+            graph.insert(
+                slot,
+                Vectors {
+                    data: &self.vectors,
+                    dimensions: self.dimensions,
+                },
+            );
+        }
+    }
+
+    /// Drops removed embeddings, rebuilding the graph.
+    fn compact(&mut self) {
+        let mut index = Index {
+            dimensions: self.dimensions,
+            vectors: Vec::with_capacity(self.slots.len() * self.dimensions),
+            entries: Vec::with_capacity(self.slots.len()),
+            slots: HashMap::with_capacity(self.slots.len()),
+            next_id: self.next_id,
+            graph: self.graph.as_ref().map(|graph| Graph::new(graph.params)),
+        };
+
+        for (slot, entry) in std::mem::take(&mut self.entries).into_iter().enumerate() {
+            if let Some(entry) = entry {
+                let start = slot * self.dimensions;
+                let vector = self.vectors[start..start + self.dimensions].iter().copied();
+
+                index.insert(entry.id, vector, entry.payload);
+            }
+        }
+
+        *self = index;
+    }
+
+    /// Returns the slots and entries of the embeddings that weren't removed, in the order they were added.
+    fn live_entries(&self) -> impl Iterator<Item = (usize, &Entry<P>)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, entry)| Some((slot, entry.as_ref()?)))
+    }
+
+    fn vectors(&self) -> Vectors<'_> {
+        Vectors {
+            data: &self.vectors,
+            dimensions: self.dimensions,
+        }
+    }
+}
+
+impl<P> Default for Index<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hnsw {
+    /// Returns parameters for a graph searched with `metric`, with 16 neighbors per embedding,
+    /// 100 candidates when adding embeddings and 50 when searching.
+    pub fn new(metric: Metric) -> Self {
+        Hnsw {
+            metric,
+            m: 16,
+            ef_construction: 100,
+            ef_search: 50,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Vectors<'a> {
+    data: &'a [f32],
+    dimensions: usize,
+}
+
+impl<'a> Vectors<'a> {
+    fn get(self, slot: usize) -> &'a [f32] {
+        &self.data[slot * self.dimensions..(slot + 1) * self.dimensions]
+    }
+}
+
+/// A slot and its distance to a query, which is lower for closer vectors with any metric.
+#[derive(Debug, Clone, Copy)]
+struct Scored {
+    distance: f64,
+    slot: usize,
+}
+
+impl Scored {
+    fn new(metric: Metric, query: &[f32], vector: &[f32], slot: usize) -> Self {
+        let score = metric
+            .compute(query, vector)
+            .expect("vectors of an index have the same dimensions");
+
+        Scored {
+            distance: if metric.higher_is_closer() {
+                -score
+            } else {
+                score
+            },
+            slot,
+        }
+    }
+
+    fn score(self, metric: Metric) -> f64 {
+        if metric.higher_is_closer() {
+            -self.distance
+        } else {
+            self.distance
+        }
+    }
+}
+
+impl PartialEq for Scored {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then(self.slot.cmp(&other.slot))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Graph {
+    params: Hnsw,
+    /// The neighbors of every slot on every layer it is on, from the lowest layer up.
+    neighbors: Vec<Vec<Vec<usize>>>,
+    /// The slot on the highest layer, where searches start.
+    entry: Option<usize>,
+    /// The state of the random number generator picking the layers of slots.
+    seed: u64,
+}
+
+impl Graph {
+    fn new(params: Hnsw) -> Self {
+        Graph {
+            params,
+            neighbors: Vec::new(),
+            entry: None,
+            seed: 0,
+        }
+    }
+
+    fn insert(&mut self, slot: usize, vectors: Vectors) {
+        let level = self.random_level();
+
+        self.neighbors.push(vec![Vec::new(); level + 1]);
+
+        let Some(entry) = self.entry else {
+            self.entry = Some(slot);
+            return;
+        };
+        let top = self.neighbors[entry].len() - 1;
+        let query = vectors.get(slot);
+        let mut entry_points = vec![entry];
+
+        for layer in (level + 1..=top).rev() {
+            entry_points = vec![self.search_layer(query, &entry_points, 1, layer, vectors)[0].slot];
+        }
+
+        for layer in (0..=level.min(top)).rev() {
+            let found = self.search_layer(
+                query,
+                &entry_points,
+                self.params.ef_construction,
+                layer,
+                vectors,
+            );
+            let max_neighbors = if layer == 0 {
+                2 * self.params.m
+            } else {
+                self.params.m
+            };
+            let neighbors: Vec<usize> = found
+                .iter()
+                .take(self.params.m)
+                .map(|scored| scored.slot)
+                .collect();
+
+            for &neighbor in &neighbors {
+                self.neighbors[neighbor][layer].push(slot);
+
+                if self.neighbors[neighbor][layer].len() > max_neighbors {
+                    self.prune(neighbor, layer, max_neighbors, vectors);
+                }
+            }
+
+            self.neighbors[slot][layer] = neighbors;
+            entry_points = found.into_iter().map(|scored| scored.slot).collect();
+        }
+
+        if level > top {
+            self.entry = Some(slot);
+        }
+    }
+
+    /// Returns at least `k` slots close to `query`, from closest to farthest.
+    fn search(&self, query: &[f32], k: usize, vectors: Vectors) -> Vec<Scored> {
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+        let mut entry_points = vec![entry];
+
+        for layer in (1..self.neighbors[entry].len()).rev() {
+            entry_points = vec![self.search_layer(query, &entry_points, 1, layer, vectors)[0].slot];
+        }
+
+        self.search_layer(
+            query,
+            &entry_points,
+            self.params.ef_search.max(k),
+            0,
+            vectors,
+        )
+    }
+
+    /// Returns the `ef` slots closest to `query` on a layer, from closest to farthest,
+    /// following neighbors from `entry_points` as long as they get closer.
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entry_points: &[usize],
+        ef: usize,
+        layer: usize,
+        vectors: Vectors,
+    ) -> Vec<Scored> {
+        let metric = self.params.metric;
+        let mut visited: HashSet<usize> = entry_points.iter().copied().collect();
+        let mut candidates = BinaryHeap::new();
+        let mut found = BinaryHeap::new();
+
+        for &slot in entry_points {
+            let scored = Scored::new(metric, query, vectors.get(slot), slot);
+
+            candidates.push(Reverse(scored));
+            found.push(scored);
+        }
+
+        while let Some(Reverse(candidate)) = candidates.pop() {
+            if found.len() >= ef && found.peek().is_some_and(|farthest| candidate > *farthest) {
+                break;
+            }
+
+            let neighbors = self.neighbors[candidate.slot]
+                .get(layer)
+                .map_or(&[][..], Vec::as_slice);
+
+            for &neighbor in neighbors {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+
+                let scored = Scored::new(metric, query, vectors.get(neighbor), neighbor);
+
+                if found.len() < ef || found.peek().is_some_and(|farthest| scored < *farthest) {
+                    candidates.push(Reverse(scored));
+                    found.push(scored);
+
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+
+        found.into_sorted_vec()
+    }
+
+    /// Keeps only the `max_neighbors` closest neighbors of a slot on a layer.
+    fn prune(&mut self, slot: usize, layer: usize, max_neighbors: usize, vectors: Vectors) {
+        let vector = vectors.get(slot);
+        let mut neighbors: Vec<Scored> = self.neighbors[slot][layer]
+            .iter()
+            .map(|neighbor| {
+                Scored::new(
+                    self.params.metric,
+                    vector,
+                    vectors.get(*neighbor),
+                    *neighbor,
+                )
+            })
+            .collect();
+
+        neighbors.sort_unstable();
+        self.neighbors[slot][layer] = neighbors
+            .into_iter()
+            .take(max_neighbors)
+            .map(|scored| scored.slot)
+            .collect();
+    }
+
+    /// Picks the layer of a new slot, with exponentially fewer slots on higher layers.
+    fn random_level(&mut self) -> usize {
+        // SplitMix64, which is plenty random to spread slots over layers
+        self.seed = self.seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.seed;
+
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        let uniform = ((z >> 11) + 1) as f64 / (1u64 << 53) as f64;
+
+        (-uniform.ln() / (self.params.m.max(2) as f64).ln()) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embedding(vec: &[f64]) -> Embedding {
+        Embedding { vec: vec.to_vec() }
+    }
+
+    /// Returns `count` pseudo-random embeddings with `dimensions`.
+    fn random_embeddings(count: usize, dimensions: usize) -> Vec<Embedding> {
+        let mut state = 1u32;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f64 / u32::MAX as f64 - 0.5
+        };
+
+        (0..count)
+            .map(|_| Embedding {
+                vec: (0..dimensions).map(|_| random()).collect(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn searches() {
+        let mut index = Index::new();
+        let east = index.add(&embedding(&[1.0, 0.0]), "east").unwrap();
+        let north = index.add(&embedding(&[0.0, 1.0]), "north").unwrap();
+
+        index.add(&embedding(&[-1.0, 0.0]), "west").unwrap();
+
+        let query = embedding(&[0.6, 0.8]);
+        let results = index.search(&query, 2, Metric::Cosine).unwrap();
+
+        assert_eq!(
+            results.iter().map(|result| result.id).collect::<Vec<_>>(),
+            [north, east]
+        );
+        assert!((results[0].score - 0.8).abs() < 1e-6);
+
+        let results = index.search(&query, 5, Metric::Euclidean).unwrap();
+
+        assert_eq!(
+            results
+                .iter()
+                .map(|result| *result.payload)
+                .collect::<Vec<_>>(),
+            ["north", "east", "west"]
+        );
+        assert!(results[0].score < results[1].score);
+        assert_eq!(index.remove(north), Some("north"));
+        assert_eq!(index.remove(north), None);
+        assert_eq!(
+            *index.search(&query, 1, Metric::Dot).unwrap()[0].payload,
+            "east"
+        );
+        assert_eq!(index.len(), 2);
+        assert!(matches!(
+            index.add(&embedding(&[1.0]), "nowhere"),
+            Err(Error::DimensionMismatch {
+                expected: 2,
+                actual: 1
+            })
+        ));
+        assert!(index
+            .search(&embedding(&[1.0, 2.0, 3.0]), 1, Metric::Dot)
+            .is_err());
+    }
+
+    #[test]
+    fn compaction() {
+        let mut index = Index::with_hnsw(Hnsw::new(Metric::Euclidean));
+        let ids: Vec<u64> = random_embeddings(30, 4)
+            .iter()
+            .enumerate()
+            .map(|(i, embedding)| index.add(embedding, i).unwrap())
+            .collect();
+
+        for id in &ids[..25] {
+            index.remove(*id);
+        }
+
+        assert_eq!(index.len(), 5);
+        assert!(index.entries.len() <= 10);
+        assert_eq!(index.get(ids[27]), Some(&27));
+        assert_eq!(index.get(ids[3]), None);
+        assert_eq!(
+            index
+                .search(&random_embeddings(1, 4)[0], 10, Metric::Euclidean)
+                .unwrap()
+                .len(),
+            5
+        );
+        assert!(index.add(&random_embeddings(1, 4)[0], 30).unwrap() > ids[29]);
+    }
+
+    #[test]
+    fn approximate_searches() {
+        let embeddings = random_embeddings(1000, 16);
+        let (queries, embeddings) = embeddings.split_at(50);
+        let mut exact = Index::new();
+        let mut approximate = Index::with_hnsw(Hnsw::new(Metric::Cosine));
+
+        for embedding in embeddings {
+            exact.add(embedding, ()).unwrap();
+            approximate.add(embedding, ()).unwrap();
+        }
+
+        let mut hits = 0;
+
+        for query in queries {
+            let expected: HashSet<u64> = exact
+                .search(query, 10, Metric::Cosine)
+                .unwrap()
+                .iter()
+                .map(|result| result.id)
+                .collect();
+            let results = approximate.search(query, 10, Metric::Cosine).unwrap();
+
+            assert_eq!(results.len(), 10);
+            assert!(results
+                .windows(2)
+                .all(|pair| pair[0].score >= pair[1].score));
+            hits += results
+                .iter()
+                .filter(|result| expected.contains(&result.id))
+                .count();
+        }
+
+        // At least 95% of the exact results are found
+        assert!(hits >= 475, "{hits} of 500 results found");
+    }
+}
@@ -0,0 +1,372 @@
+use super::{Hnsw, Index};
+use crate::embeddings::Metric;
+use half::f16;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+};
+
+const MAGIC: &[u8; 4] = b"OAIX";
+const VERSION: u16 = 1;
+
+/// How the vectors of an [`Index`] are stored in a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Quantization {
+    /// Vectors are stored as `f32`, exactly as they are in memory.
+    #[default]
+    None,
+    /// Vectors are stored as `f16`, which halves the size of the file,
+    /// at the cost of about 3 significant digits of precision.
+    F16,
+}
+
+impl<P: Serialize> Index<P> {
+    /// Writes the index to a file at `path`, see [`Index::write`].
+    pub fn save(&self, path: impl AsRef<Path>, quantization: Quantization) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        self.write(&mut writer, quantization)?;
+        writer.flush()
+    }
+
+    /// Writes the index in a compact binary format, which can be read back with [`Index::read`].
+    ///
+    /// The format starts with a versioned header, followed by the IDs and vectors of the embeddings,
+    /// all little-endian, and ends with the payloads as JSON lines.
+    /// The graph of an index created with [`Index::with_hnsw`] isn't written, but is rebuilt when the index is read.
+    pub fn write(&self, mut writer: impl Write, quantization: Quantization) -> io::Result<()> {
+        let hnsw = self.graph.as_ref().map(|graph| graph.params);
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&[
+            match quantization {
+                Quantization::None => 0,
+                Quantization::F16 => 1,
+            },
+            hnsw.is_some().into(),
+        ])?;
+        writer.write_all(&[match hnsw.map_or(Metric::Cosine, |hnsw| hnsw.metric) {
+            Metric::Cosine => 0,
+            Metric::Dot => 1,
+            Metric::Euclidean => 2,
+            Metric::Manhattan => 3,
+        }])?;
+
+        for parameter in [
+            hnsw.map_or(0, |hnsw| hnsw.m),
+            hnsw.map_or(0, |hnsw| hnsw.ef_construction),
+            hnsw.map_or(0, |hnsw| hnsw.ef_search),
+            self.dimensions,
+        ] {
+            writer.write_all(&(parameter as u32).to_le_bytes())?;
+        }
+
+        writer.write_all(&(self.len() as u64).to_le_bytes())?;
+        writer.write_all(&self.next_id.to_le_bytes())?;
+
+        for (_, entry) in self.live_entries() {
+            writer.write_all(&entry.id.to_le_bytes())?;
+        }
+
+        for (slot, _) in self.live_entries() {
+            for value in self.vectors().get(slot) {
+                match quantization {
+                    Quantization::None => writer.write_all(&value.to_le_bytes())?,
+                    Quantization::F16 => writer.write_all(&f16::from_f32(*value).to_le_bytes())?,
+                }
+            }
+        }
+
+        for (_, entry) in self.live_entries() {
+            serde_json::to_writer(&mut writer, &entry.payload)?;
+            writer.write_all(b"\n")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<P: DeserializeOwned> Index<P> {
+    /// Reads an index from a file at `path`, see [`Index::read`].
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Reads an index written by [`Index::write`].
+    ///
+    /// Fails with [`ErrorKind::InvalidData`] if the data isn't an index, was written by an incompatible version,
+    /// or a payload isn't a valid `P`.
+    pub fn read(mut reader: impl BufRead) -> io::Result<Self> {
+        if read_array::<4>(&mut reader)? != *MAGIC {
+            return Err(invalid_data("not an embedding index"));
+        }
+
+        let version = u16::from_le_bytes(read_array(&mut reader)?);
+
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported embedding index version {version}"
+            )));
+        }
+
+        let [quantization, has_hnsw, metric] = read_array(&mut reader)?;
+        let quantization = match quantization {
+            0 => Quantization::None,
+            1 => Quantization::F16,
+            _ => return Err(invalid_data("unknown quantization")),
+        };
+        let has_hnsw = match has_hnsw {
+            0 => false,
+            1 => true,
+            _ => return Err(invalid_data("invalid HNSW flag")),
+        };
+        let metric = match metric {
+            0 => Metric::Cosine,
+            1 => Metric::Dot,
+            2 => Metric::Euclidean,
+            3 => Metric::Manhattan,
+            _ => return Err(invalid_data("unknown metric")),
+        };
+        let [m, ef_construction, ef_search, dimensions] =
+            read_u32s::<4>(&mut reader)?.map(|parameter| parameter as usize);
+        let count = u64::from_le_bytes(read_array(&mut reader)?);
+        let (count, values) = usize::try_from(count)
+            .ok()
+            .and_then(|count| Some((count, count.checked_mul(dimensions)?)))
+            .ok_or_else(|| invalid_data(format!("too many vectors: {count}")))?;
+        let next_id = u64::from_le_bytes(read_array(&mut reader)?);
+        let mut index = if has_hnsw {
+            Index::with_hnsw(Hnsw {
+                metric,
+                m,
+                ef_construction,
+                ef_search,
+            })
+        } else {
+            Index::new()
+        };
+        let mut ids = Vec::new();
+        let mut seen = HashSet::new();
+
+        for _ in 0..count {
+            let id = u64::from_le_bytes(read_array(&mut reader)?);
+
+            if !seen.insert(id) {
+                return Err(invalid_data(format!("duplicate id {id}")));
+            }
+
+            ids.push(id);
+        }
+
+        let mut vectors = Vec::new();
+
+        for _ in 0..values {
+            vectors.push(match quantization {
+                Quantization::None => f32::from_le_bytes(read_array(&mut reader)?),
+                Quantization::F16 => f16::from_le_bytes(read_array(&mut reader)?).to_f32(),
+            });
+        }
+
+        let mut line = String::new();
+
+        index.dimensions = dimensions;
+
+        for (i, id) in ids.into_iter().enumerate() {
+            line.clear();
+
+            if reader.read_line(&mut line)? == 0 {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+
+            let payload = serde_json::from_str(&line)?;
+            let vector = vectors[i * dimensions..(i + 1) * dimensions]
+                .iter()
+                .copied();
+
+            index.insert(id, vector, payload);
+        }
+
+        index.next_id = index.next_id.max(next_id);
+
+        Ok(index)
+    }
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+
+    reader.read_exact(&mut bytes)?;
+
+    Ok(bytes)
+}
+
+fn read_u32s<const N: usize>(reader: &mut impl Read) -> io::Result<[u32; N]> {
+    let mut values = [0; N];
+
+    for value in &mut values {
+        *value = u32::from_le_bytes(read_array(reader)?);
+    }
+
+    Ok(values)
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embeddings::Embedding;
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Document {
+        title: String,
+        page: u32,
+    }
+
+    fn index(hnsw: Option<Hnsw>) -> Index<Document> {
+        let mut index = hnsw.map_or_else(Index::new, Index::with_hnsw);
+
+        for (i, vec) in [[0.1, 0.2, 0.3], [-0.5, 0.25, 1.0], [0.7, -0.1, 0.0]]
+            .into_iter()
+            .enumerate()
+        {
+            let document = Document {
+                title: format!("Document\n{i}"),
+                page: i as u32,
+            };
+
+            index
+                .add(&Embedding { vec: vec.to_vec() }, document)
+                .unwrap();
+        }
+
+        index.remove(1);
+        index
+    }
+
+    #[test]
+    fn round_trips() {
+        let index = index(None);
+        let mut bytes = Vec::new();
+
+        index.write(&mut bytes, Quantization::None).unwrap();
+
+        let read = Index::<Document>::read(bytes.as_slice()).unwrap();
+
+        assert_eq!(read.len(), 2);
+        assert_eq!(read.dimensions(), Some(3));
+        assert_eq!(read.get(1), None);
+        assert_eq!(read.get(2), index.get(2));
+        assert_eq!(read.vectors().get(1), index.vectors().get(2));
+        assert!(read.graph.is_none());
+
+        let mut read = read;
+        let document = Document {
+            title: String::new(),
+            page: 3,
+        };
+
+        // IDs of removed embeddings aren't reused
+        assert_eq!(
+            read.add(&Embedding { vec: vec![0.0; 3] }, document)
+                .unwrap(),
+            3
+        );
+    }
+
+    #[test]
+    fn quantized_round_trips() {
+        let hnsw = Hnsw::new(Metric::Euclidean);
+        let index = index(Some(hnsw));
+        let path = std::env::temp_dir().join(format!("openai-index-{}.bin", std::process::id()));
+
+        index.save(&path, Quantization::F16).unwrap();
+
+        let loaded = Index::<Document>::load(&path).unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.graph.as_ref().unwrap().params, hnsw);
+        assert_eq!(loaded.get(0), index.get(0));
+
+        let vectors = index
+            .live_entries()
+            .flat_map(|(slot, _)| index.vectors().get(slot));
+
+        assert_eq!(loaded.vectors.len(), 6);
+
+        for (a, b) in loaded.vectors.iter().zip(vectors) {
+            assert!((a - b).abs() < 1e-3, "{a} and {b} differ");
+        }
+
+        let query = Embedding {
+            vec: vec![0.7, -0.1, 0.0],
+        };
+
+        assert_eq!(
+            loaded.search(&query, 1, Metric::Euclidean).unwrap()[0].id,
+            2
+        );
+    }
+
+    #[test]
+    fn invalid_files() {
+        let mut bytes = Vec::new();
+
+        index(None).write(&mut bytes, Quantization::None).unwrap();
+
+        let error = Index::<Document>::read(&b"JUNK"[..]).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        let mut newer = bytes.clone();
+
+        newer[4] = 2;
+        assert_eq!(
+            Index::<Document>::read(newer.as_slice())
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidData
+        );
+        let mut huge = bytes.clone();
+
+        huge[25..33].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(
+            Index::<Document>::read(huge.as_slice()).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        let mut flagged = bytes.clone();
+
+        flagged[7] = 2;
+        assert_eq!(
+            Index::<Document>::read(flagged.as_slice())
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidData
+        );
+
+        // The ids follow the header, which ends with the count and the next id
+        let mut duplicate = bytes.clone();
+        let first_id = duplicate[41..49].to_vec();
+
+        duplicate[49..57].copy_from_slice(&first_id);
+        assert_eq!(
+            Index::<Document>::read(duplicate.as_slice())
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidData
+        );
+        assert!(Index::<Document>::read(&bytes[..bytes.len() - 5]).is_err());
+        assert_eq!(
+            Index::<u32>::read(bytes.as_slice()).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+}
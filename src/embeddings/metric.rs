@@ -0,0 +1,117 @@
+use crate::Error;
+
+/// A way to compare two embeddings, see [`Embedding::score`](super::Embedding::score).
+///
+/// Vectors of `f32` or `f64` can be compared; either way, the sums are computed in `f64` with compensated summation,
+/// so that long vectors don't lose precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Metric {
+    /// The cosine of the angle between the vectors, from -1 to 1, where 1 means they point in the same direction.
+    /// This is 0 if either vector is zero.
+    ///
+    /// OpenAI embeddings are normalized to a length of 1, so this ranks them like [`Metric::Dot`].
+    #[default]
+    Cosine,
+    /// The dot product of the vectors, which is higher for closer vectors.
+    Dot,
+    /// The straight-line distance between the vectors, which is lower for closer vectors.
+    Euclidean,
+    /// The sum of the absolute differences of the vectors, which is lower for closer vectors.
+    Manhattan,
+}
+
+impl Metric {
+    /// Compares `a` and `b`, failing with [`Error::DimensionMismatch`] if they have different lengths.
+    pub fn compute<T>(self, a: &[T], b: &[T]) -> Result<f64, Error>
+    where
+        T: Copy + Into<f64>,
+    {
+        if a.len() != b.len() {
+            return Err(Error::DimensionMismatch {
+                expected: a.len(),
+                actual: b.len(),
+            });
+        }
+
+        let pairs = || a.iter().zip(b).map(|(x, y)| ((*x).into(), (*y).into()));
+
+        Ok(match self {
+            Metric::Cosine => {
+                let dot = sum(pairs().map(|(x, y)| x * y));
+                let norms = sum(pairs().map(|(x, _)| x * x)).sqrt()
+                    * sum(pairs().map(|(_, y)| y * y)).sqrt();
+
+                if norms == 0.0 {
+                    0.0
+                } else {
+                    (dot / norms).clamp(-1.0, 1.0)
+                }
+            }
+            Metric::Dot => sum(pairs().map(|(x, y)| x * y)),
+            Metric::Euclidean => sum(pairs().map(|(x, y)| (x - y) * (x - y))).sqrt(),
+            Metric::Manhattan => sum(pairs().map(|(x, y)| (x - y).abs())),
+        })
+    }
+
+    /// Whether higher values mean closer vectors, which is the case for [`Metric::Cosine`] and [`Metric::Dot`].
+    pub fn higher_is_closer(self) -> bool {
+        matches!(self, Metric::Cosine | Metric::Dot)
+    }
+}
+
+/// Adds up `values` with Neumaier's compensated summation,
+/// which keeps track of the low-order bits lost by every addition.
+fn sum(values: impl Iterator<Item = f64>) -> f64 {
+    let mut sum = 0.0;
+    let mut compensation = 0.0;
+
+    for value in values {
+        let total = sum + value;
+
+        if f64::abs(sum) >= f64::abs(value) {
+            compensation += (sum - total) + value;
+        } else {
+            compensation += (value - total) + sum;
+        }
+
+        sum = total;
+    }
+
+    sum + compensation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics() {
+        let a = [3.0, 4.0];
+        let b = [4.0, 3.0];
+
+        assert_eq!(Metric::Cosine.compute(&a, &b).unwrap(), 0.96);
+        assert_eq!(Metric::Cosine.compute(&a, &[6.0, 8.0]).unwrap(), 1.0);
+        assert_eq!(Metric::Cosine.compute(&a, &[0.0, 0.0]).unwrap(), 0.0);
+        assert_eq!(Metric::Dot.compute(&a, &b).unwrap(), 24.0);
+        assert_eq!(Metric::Euclidean.compute(&a, &b).unwrap(), 2f64.sqrt());
+        assert_eq!(Metric::Manhattan.compute(&a, &b).unwrap(), 2.0);
+        assert_eq!(
+            Metric::Dot.compute(&[0.5f32, 0.25], &[2.0, 4.0]).unwrap(),
+            2.0
+        );
+        assert!(matches!(
+            Metric::Cosine.compute(&a, &[1.0]),
+            Err(Error::DimensionMismatch {
+                expected: 2,
+                actual: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn compensated_sums() {
+        let values = std::iter::once(1.0).chain(std::iter::repeat_n(1e-16, 10_000));
+
+        assert_eq!(sum(values), 1.000000000001);
+    }
+}
@@ -1,25 +1,311 @@
-use reqwest::{header::AUTHORIZATION, Client, Method, RequestBuilder};
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use std::sync::Mutex;
+use derive_builder::UninitializedFieldError;
+use futures_util::{stream, Stream, StreamExt};
+use reqwest::{header::AUTHORIZATION, Client, Method, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::{collections::HashMap, env, pin::Pin, sync::Mutex, time::Duration};
 
 pub mod chat;
 pub mod completions;
 pub mod edits;
 pub mod embeddings;
+mod logit_bias;
 pub mod models;
 pub mod moderations;
+mod rate_limit;
+mod response;
+mod retry;
+#[cfg(test)]
+mod stub;
+#[cfg(feature = "tokenizer")]
+pub mod tokenizer;
+mod validate;
+
+pub use logit_bias::LogitBias;
+pub use rate_limit::{RateLimit, RateLimiter};
+pub use response::{Response, ResponseMetadata};
+pub use retry::RetryPolicy;
+pub use validate::{Validate, ValidationError};
+
+/// Used by the code generated by `openai-derive`.
+#[doc(hidden)]
+pub mod __private {
+    pub use serde;
+    pub use serde_json;
+}
 
 const BASE_URL: &str = "https://api.openai.com/v1/";
 
-static API_KEY: Mutex<String> = Mutex::new(String::new());
+static DEFAULT_CLIENT: Mutex<Option<OpenAiClient>> = Mutex::new(None);
+
+/// The key and connection used to make requests to the OpenAI API.
+///
+/// Requests use the default client configured with [`set_key`] unless they are given a client,
+/// so several keys can be used side by side in one process.
+/// Clones are cheap and share a pool of connections.
+///
+/// ## Examples
+///
+/// ```rust
+/// use openai::{chat::ChatCompletion, OpenAiClient};
+///
+/// let client = OpenAiClient::new("sk-...");
+/// let builder = ChatCompletion::builder("gpt-3.5-turbo", []).client(&client);
+/// ```
+#[derive(Clone)]
+pub struct OpenAiClient {
+    key: String,
+    org: Option<String>,
+    project: Option<String>,
+    base_url: String,
+    azure: Option<Azure>,
+    timeout: Option<Duration>,
+    retry_policy: RetryPolicy,
+    rate_limiter: Option<RateLimiter>,
+    http: Client,
+}
+
+#[derive(Clone, Debug)]
+struct Azure {
+    api_version: String,
+    /// Deployment names by model, for deployments not named after their model.
+    deployments: HashMap<String, String>,
+}
+
+impl OpenAiClient {
+    /// Creates a client using `key`.
+    ///
+    /// Requests are sent to the URL in the `OPENAI_BASE_URL` environment variable if it is set,
+    /// or to the OpenAI API otherwise.
+    /// They count towards the organization and project in the `OPENAI_ORG_ID` and `OPENAI_PROJECT_ID`
+    /// environment variables if they are set, or towards the defaults of the key otherwise.
+    pub fn new(key: impl Into<String>) -> Self {
+        Self::with_env(key, |name| env::var(name).ok())
+    }
+
+    /// Like [`OpenAiClient::new`], but environment variables are looked up with `var`,
+    /// so that tests don't depend on the environment of the process.
+    fn with_env(key: impl Into<String>, var: impl Fn(&str) -> Option<String>) -> Self {
+        OpenAiClient {
+            key: key.into(),
+            org: var("OPENAI_ORG_ID"),
+            project: var("OPENAI_PROJECT_ID"),
+            base_url: var("OPENAI_BASE_URL").unwrap_or_else(|| BASE_URL.to_string()),
+            azure: None,
+            timeout: None,
+            retry_policy: RetryPolicy::never(),
+            rate_limiter: None,
+            http: Client::new(),
+        }
+    }
+
+    /// Creates a client for an [Azure OpenAI](https://learn.microsoft.com/azure/cognitive-services/openai/) resource,
+    /// using one of its keys and an `api_version` such as `2023-05-15`.
+    ///
+    /// Requests are sent to the deployment with the same name as the requested model,
+    /// unless a different one is set with [`OpenAiClient::deployment`].
+    ///
+    /// ## Examples
+    ///
+    /// ```rust
+    /// use openai::OpenAiClient;
+    ///
+    /// let client = OpenAiClient::azure("https://my-resource.openai.azure.com", "...", "2023-05-15")
+    ///     .deployment("gpt-3.5-turbo", "my-gpt-35-turbo");
+    /// ```
+    pub fn azure(
+        endpoint: impl Into<String>,
+        key: impl Into<String>,
+        api_version: impl Into<String>,
+    ) -> Self {
+        OpenAiClient {
+            key: key.into(),
+            org: None,
+            project: None,
+            base_url: endpoint.into(),
+            azure: Some(Azure {
+                api_version: api_version.into(),
+                deployments: HashMap::new(),
+            }),
+            timeout: None,
+            retry_policy: RetryPolicy::never(),
+            rate_limiter: None,
+            http: Client::new(),
+        }
+    }
+
+    /// Sends requests for `model` to the Azure deployment named `deployment`.
+    ///
+    /// This has no effect on clients not created with [`OpenAiClient::azure`].
+    pub fn deployment(mut self, model: impl Into<String>, deployment: impl Into<String>) -> Self {
+        if let Some(azure) = &mut self.azure {
+            azure.deployments.insert(model.into(), deployment.into());
+        }
+
+        self
+    }
+
+    /// Sets the URL that routes such as `chat/completions` are appended to,
+    /// for example to use a proxy or a server compatible with the OpenAI API.
+    ///
+    /// ```rust
+    /// use openai::OpenAiClient;
+    ///
+    /// let client = OpenAiClient::new("sk-...").base_url("http://localhost:8080/v1");
+    /// ```
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Fails requests with [`Error::Timeout`] if they take longer than `timeout` to complete.
+    /// For streams, this includes the time taken to receive every event.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how requests that fail for a transient reason are retried. By default, they are not.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Makes requests wait for the rate limits of `rate_limiter` before being sent,
+    /// instead of being rejected by the API once a limit is reached.
+    ///
+    /// The limiter can be shared by several clients that use the same limits, such as those of one organization.
+    pub fn rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Sets the organization whose subscription requests made with this client should count towards.
+    pub fn organization(mut self, org: impl Into<String>) -> Self {
+        self.org = Some(org.into());
+        self
+    }
+
+    /// Sets the project that requests made with this client should count towards.
+    pub fn project(mut self, project: impl Into<String>) -> Self {
+        self.project = Some(project.into());
+        self
+    }
+
+    /// Returns the URL of `route`. Azure routes are scoped to the deployment of `model`.
+    fn url(&self, route: &str, model: Option<&str>) -> String {
+        let base_url = self.base_url.trim_end_matches('/');
+        let route = route.trim_start_matches('/');
+
+        match (&self.azure, model) {
+            (Some(azure), Some(model)) => {
+                let deployment = azure.deployments.get(model).map_or(model, String::as_str);
+
+                format!("{base_url}/openai/deployments/{deployment}/{route}")
+            }
+            (Some(_), None) => format!("{base_url}/openai/{route}"),
+            (None, _) => format!("{base_url}/{route}"),
+        }
+    }
+}
+
+impl From<&OpenAiClient> for OpenAiClient {
+    fn from(client: &OpenAiClient) -> Self {
+        client.clone()
+    }
+}
+
+impl std::fmt::Debug for OpenAiClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OpenAiClient")
+            .field("org", &self.org)
+            .field("project", &self.project)
+            .field("base_url", &self.base_url)
+            .field("azure", &self.azure)
+            .field("timeout", &self.timeout)
+            .field("retry_policy", &self.retry_policy)
+            .field("rate_limiter", &self.rate_limiter)
+            .finish_non_exhaustive()
+    }
+}
+
+fn default_client() -> OpenAiClient {
+    DEFAULT_CLIENT
+        .lock()
+        .unwrap()
+        .get_or_insert_with(|| OpenAiClient::new(""))
+        .clone()
+}
+
+/// Returns the client to make a request with,
+/// which is `client` or the default client with the `organization` and `project` the request overrides.
+/// Requests that override neither are made with `client` as it is.
+fn request_client(
+    client: Option<&OpenAiClient>,
+    organization: Option<&String>,
+    project: Option<&String>,
+) -> Option<OpenAiClient> {
+    if organization.is_none() && project.is_none() {
+        return client.cloned();
+    }
+
+    let mut client = client.cloned().unwrap_or_else(default_client);
+
+    if let Some(organization) = organization {
+        client.org = Some(organization.clone());
+    }
+
+    if let Some(project) = project {
+        client.project = Some(project.clone());
+    }
+
+    Some(client)
+}
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct OpenAiError {
     pub message: String,
-    #[serde(rename = "type")]
+    /// Empty for errors from Azure, which do not have a type.
+    #[serde(rename = "type", default, deserialize_with = "null_as_default")]
     pub error_type: String,
+    #[serde(default)]
     pub param: Option<String>,
+    #[serde(default)]
     pub code: Option<String>,
+    /// Details that Azure adds to some errors,
+    /// such as which content filters a prompt was rejected by when `code` is `content_filter`.
+    #[serde(rename = "innererror", default)]
+    pub inner_error: Option<Box<AzureInnerError>>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AzureInnerError {
+    pub code: Option<String>,
+    pub content_filter_result: Option<ContentFilterResults>,
+}
+
+/// The results of Azure's content filters for a prompt or completion.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ContentFilterResults {
+    pub hate: Option<ContentFilterResult>,
+    pub self_harm: Option<ContentFilterResult>,
+    pub sexual: Option<ContentFilterResult>,
+    pub violence: Option<ContentFilterResult>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ContentFilterResult {
+    pub filtered: bool,
+    /// One of `safe`, `low`, `medium` or `high`.
+    pub severity: String,
+}
+
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Ok(Option::deserialize(deserializer)?.unwrap_or_default())
 }
 
 impl std::fmt::Display for OpenAiError {
@@ -30,6 +316,117 @@ impl std::fmt::Display for OpenAiError {
 
 impl std::error::Error for OpenAiError {}
 
+/// An error returned by the functions of this crate.
+#[derive(Debug)]
+pub enum Error {
+    /// The API responded with an error.
+    Api {
+        status: StatusCode,
+        /// Includes the `error_type`, `code` and `param` of the error.
+        error: OpenAiError,
+    },
+    /// The request could not be sent, or the response could not be received.
+    Transport(reqwest::Error),
+    /// The response could not be decoded,
+    /// for example because a proxy responded with an HTML error page.
+    Decode {
+        status: StatusCode,
+        /// The raw response body, or the data of the event that could not be decoded.
+        body: String,
+        source: serde_json::Error,
+    },
+    /// The request did not complete within the [timeout](OpenAiClient::timeout) of its client.
+    Timeout(reqwest::Error),
+    /// A request was invalid, for example because one of its required fields was not set.
+    Builder(String),
+    /// A request was found to be invalid before it was sent, see [`Validate`].
+    Validation(ValidationError),
+    /// The model refused to answer a request made with [`create_parsed`](chat::ChatCompletionBuilder::create_parsed).
+    Refusal(String),
+    /// The message generated for a request made with [`create_parsed`](chat::ChatCompletionBuilder::create_parsed)
+    /// could not be deserialized, for example because it is not JSON or was cut off.
+    InvalidOutput {
+        content: String,
+        source: serde_json::Error,
+    },
+    /// An [`Agent`](chat::agent::Agent) used up its budget of iterations or tokens before the model answered.
+    BudgetExceeded { iterations: u32, total_tokens: u32 },
+    /// Embeddings with different dimensions were compared, for example because they were created by different models.
+    DimensionMismatch { expected: usize, actual: usize },
+    /// The API responded successfully, but without everything that was requested,
+    /// for example a chat completion without choices.
+    IncompleteResponse(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Api { status, error } => write!(f, "{error} ({status})"),
+            Error::Transport(error) => write!(f, "request failed: {error}"),
+            Error::Decode { status, source, .. } => {
+                write!(f, "failed to decode response ({status}): {source}")
+            }
+            Error::Timeout(_) => write!(f, "request timed out"),
+            Error::Builder(message) => write!(f, "invalid request: {message}"),
+            Error::Validation(error) => write!(f, "invalid request: {error}"),
+            Error::Refusal(refusal) => write!(f, "the model refused to answer: {refusal}"),
+            Error::InvalidOutput { source, .. } => {
+                write!(f, "the model generated an invalid answer: {source}")
+            }
+            Error::BudgetExceeded {
+                iterations,
+                total_tokens,
+            } => write!(
+                f,
+                "no answer after {iterations} completions using {total_tokens} tokens"
+            ),
+            Error::DimensionMismatch { expected, actual } => write!(
+                f,
+                "expected an embedding with {expected} dimensions, but got {actual}"
+            ),
+            Error::IncompleteResponse(message) => write!(f, "incomplete response: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Api { error, .. } => Some(error),
+            Error::Transport(error) | Error::Timeout(error) => Some(error),
+            Error::Decode { source, .. } | Error::InvalidOutput { source, .. } => Some(source),
+            Error::Validation(error) => Some(error),
+            Error::Builder(_)
+            | Error::Refusal(_)
+            | Error::BudgetExceeded { .. }
+            | Error::DimensionMismatch { .. }
+            | Error::IncompleteResponse(_) => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_timeout() {
+            Error::Timeout(error)
+        } else {
+            Error::Transport(error)
+        }
+    }
+}
+
+impl From<ValidationError> for Error {
+    fn from(error: ValidationError) -> Self {
+        Error::Validation(error)
+    }
+}
+
+impl From<UninitializedFieldError> for Error {
+    fn from(error: UninitializedFieldError) -> Self {
+        Error::Builder(error.to_string())
+    }
+}
+
 #[derive(Deserialize, Clone)]
 #[serde(untagged)]
 pub enum ApiResponse<T> {
@@ -37,54 +434,330 @@ pub enum ApiResponse<T> {
     Err { error: OpenAiError },
 }
 
-#[derive(Deserialize, Clone, Copy)]
+#[derive(Deserialize)]
+struct ApiErrorResponse {
+    error: OpenAiError,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
 pub struct Usage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
 }
 
-type ApiResponseOrError<T> = Result<Result<T, OpenAiError>, reqwest::Error>;
+/// Returns the perplexity of tokens with the log probabilities `logprobs`,
+/// which is the exponential of their average negative log probability, or `None` without any tokens.
+fn perplexity(logprobs: impl IntoIterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = logprobs
+        .into_iter()
+        .fold((0.0, 0), |(sum, count), logprob| (sum + logprob, count + 1));
+
+    (count > 0).then(|| (-sum / count as f64).exp())
+}
+
+/// A stream of [server-sent events](https://developer.mozilla.org/en-US/docs/Web/API/Server-sent_events/Using_server-sent_events#Event_stream_format),
+/// each decoded as a `T`. The stream ends once the API sends `data: [DONE]`.
+pub type EventStream<T> = Pin<Box<dyn Stream<Item = Result<T, Error>> + Send>>;
+
+async fn openai_send(
+    client: Option<&OpenAiClient>,
+    method: Method,
+    route: &str,
+    body: Option<&Value>,
+) -> Result<reqwest::Response, Error> {
+    let default;
+    let client = match client {
+        Some(client) => client,
+        None => {
+            default = default_client();
+            &default
+        }
+    };
+    // The model picks the deployment for Azure
+    let model = body
+        .and_then(|body| body.get("model"))
+        .and_then(Value::as_str);
+    let mut request = client
+        .http
+        .request(method.clone(), client.url(route, model));
+
+    if let Some(body) = body {
+        request = request.json(body);
+    }
+
+    request = match &client.azure {
+        Some(azure) => request
+            .header("api-key", &client.key)
+            .query(&[("api-version", &azure.api_version)]),
+        None => request.header(AUTHORIZATION, format!("Bearer {}", client.key)),
+    };
+
+    if let Some(org) = &client.org {
+        request = request.header("OpenAI-Organization", org);
+    }
+
+    if let Some(project) = &client.project {
+        request = request.header("OpenAI-Project", project);
+    }
+
+    if let Some(timeout) = client.timeout {
+        request = request.timeout(timeout);
+    }
+
+    let tokens = body.map_or(0, rate_limit::estimate_tokens);
+    let mut attempt = 1;
+
+    loop {
+        if let Some(rate_limiter) = &client.rate_limiter {
+            rate_limiter.acquire(tokens).await;
+        }
+
+        // Request bodies are always JSON, so they can be cloned
+        let attempt_request = request.try_clone().expect("request body is not a stream");
+        let (error, headers) = match attempt_request.send().await {
+            Ok(response) => {
+                if let Some(rate_limiter) = &client.rate_limiter {
+                    rate_limiter.update(response.headers());
+                }
+
+                if !retry::retryable_status(response.status()) {
+                    return Ok(response);
+                }
+
+                let status = response.status();
+                let headers = response.headers().clone();
+
+                (decode_error(status, response.text().await?), Some(headers))
+            }
+            Err(error) => (error.into(), None),
+        };
+
+        if attempt >= client.retry_policy.max_attempts()
+            || !client.retry_policy.should_retry(&method, &error)
+        {
+            return Err(error);
+        }
+
+        tokio::time::sleep(client.retry_policy.backoff(attempt, headers.as_ref())).await;
+        attempt += 1;
+    }
+}
+
+async fn openai_request<T>(
+    client: Option<&OpenAiClient>,
+    method: Method,
+    route: &str,
+    body: Option<&Value>,
+) -> Result<Response<T>, Error>
+where
+    T: DeserializeOwned,
+{
+    let response = openai_send(client, method, route, body).await?;
+    let status = response.status();
+    let metadata = ResponseMetadata::from_headers(response.headers());
+    let body = response.text().await?;
 
-async fn openai_request<F, T>(method: Method, route: &str, builder: F) -> ApiResponseOrError<T>
+    Ok(Response {
+        body: decode(status, body)?,
+        status,
+        metadata,
+    })
+}
+
+/// Decodes a response body as a `T`, or as the error the API responded with instead.
+fn decode<T>(status: StatusCode, body: String) -> Result<T, Error>
 where
-    F: FnOnce(RequestBuilder) -> RequestBuilder,
     T: DeserializeOwned,
 {
-    let client = Client::new();
-    let mut request = client.request(method, BASE_URL.to_owned() + route);
+    if !status.is_success() {
+        return Err(decode_error(status, body));
+    }
+
+    serde_json::from_str(&body).map_err(|source| {
+        match serde_json::from_str::<ApiErrorResponse>(&body) {
+            // Errors sent in the events of a stream have a successful status
+            Ok(ApiErrorResponse { error }) => Error::Api { status, error },
+            Err(_) => Error::Decode {
+                status,
+                body,
+                source,
+            },
+        }
+    })
+}
+
+/// Decodes the body of an unsuccessful response as the error the API responded with.
+fn decode_error(status: StatusCode, body: String) -> Error {
+    match serde_json::from_str::<ApiErrorResponse>(&body) {
+        Ok(ApiErrorResponse { error }) => Error::Api { status, error },
+        Err(source) => Error::Decode {
+            status,
+            body,
+            source,
+        },
+    }
+}
+
+async fn openai_request_stream<T>(
+    client: Option<&OpenAiClient>,
+    method: Method,
+    route: &str,
+    body: Option<&Value>,
+) -> Result<Response<EventStream<T>>, Error>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    let response = openai_send(client, method, route, body).await?;
+    let status = response.status();
+
+    if !status.is_success() {
+        return Err(decode_error(status, response.text().await?));
+    }
+
+    let metadata = ResponseMetadata::from_headers(response.headers());
+
+    Ok(Response {
+        body: event_stream(response),
+        status,
+        metadata,
+    })
+}
+
+/// Turns the body of `response` into a stream of events.
+/// Events with an `error` object are yielded as an [`Error::Api`] without ending the stream.
+fn event_stream<T>(response: reqwest::Response) -> EventStream<T>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    let status = response.status();
+    let bytes = response.bytes_stream().boxed();
+
+    Box::pin(stream::unfold(
+        (bytes, Vec::new(), false),
+        move |(mut bytes, mut buffer, mut finished)| async move {
+            loop {
+                let event = match take_event(&mut buffer) {
+                    Some(event) => event,
+                    None if finished => return None,
+                    None => {
+                        match bytes.next().await {
+                            Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                            Some(Err(error)) => {
+                                return Some((Err(error.into()), (bytes, buffer, true)))
+                            }
+                            None => {
+                                // Let an event that is missing its trailing blank line through.
+                                buffer.extend_from_slice(b"\n\n");
+                                finished = true;
+                            }
+                        }
+
+                        continue;
+                    }
+                };
+                let Some(data) = event_data(&event) else {
+                    continue;
+                };
+
+                if data == "[DONE]" {
+                    return None;
+                }
+
+                return Some((decode(status, data), (bytes, buffer, finished)));
+            }
+        },
+    ))
+}
+
+/// Removes the first complete event, terminated by a blank line, from `buffer`.
+fn take_event(buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let mut line_start = 0;
+
+    while let Some(offset) = buffer[line_start..].iter().position(|&byte| byte == b'\n') {
+        let line_end = line_start + offset;
+        let line = &buffer[line_start..line_end];
+
+        if line.is_empty() || line == b"\r" {
+            let event = buffer[..line_start].to_vec();
 
-    request = builder(request);
+            buffer.drain(..=line_end);
 
-    let api_response: ApiResponse<T> = request
-        .header(AUTHORIZATION, format!("Bearer {}", API_KEY.lock().unwrap()))
-        .send()
-        .await?
-        .json()
-        .await?;
+            return Some(event);
+        }
 
-    match api_response {
-        ApiResponse::Ok(t) => Ok(Ok(t)),
-        ApiResponse::Err { error } => Ok(Err(error)),
+        line_start = line_end + 1;
     }
+
+    None
+}
+
+/// Joins the `data` fields of an event, or returns `None` if it has none.
+fn event_data(event: &[u8]) -> Option<String> {
+    let event = String::from_utf8_lossy(event);
+    let mut data: Option<String> = None;
+
+    for line in event.lines() {
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+
+        if field != "data" {
+            continue;
+        }
+
+        let value = value.strip_prefix(' ').unwrap_or(value);
+
+        match &mut data {
+            Some(data) => {
+                data.push('\n');
+                data.push_str(value);
+            }
+            None => data = Some(value.to_string()),
+        }
+    }
+
+    data
 }
 
-async fn openai_get<T>(route: &str) -> ApiResponseOrError<T>
+async fn openai_get<T>(client: Option<&OpenAiClient>, route: &str) -> Result<Response<T>, Error>
 where
     T: DeserializeOwned,
 {
-    openai_request(Method::GET, route, |request| request).await
+    openai_request(client, Method::GET, route, None).await
 }
 
-async fn openai_post<J, T>(route: &str, json: &J) -> ApiResponseOrError<T>
+async fn openai_post<J, T>(
+    client: Option<&OpenAiClient>,
+    route: &str,
+    json: &J,
+) -> Result<Response<T>, Error>
 where
     J: Serialize + ?Sized,
     T: DeserializeOwned,
 {
-    openai_request(Method::POST, route, |request| request.json(json)).await
+    openai_request(client, Method::POST, route, Some(&to_body(json)?)).await
 }
 
-/// Sets the key for all OpenAI API functions.
+async fn openai_post_stream<J, T>(
+    client: Option<&OpenAiClient>,
+    route: &str,
+    json: &J,
+) -> Result<Response<EventStream<T>>, Error>
+where
+    J: Serialize + ?Sized,
+    T: DeserializeOwned + Send + 'static,
+{
+    openai_request_stream(client, Method::POST, route, Some(&to_body(json)?)).await
+}
+
+fn to_body<J>(json: &J) -> Result<Value, Error>
+where
+    J: Serialize + ?Sized,
+{
+    serde_json::to_value(json)
+        .map_err(|error| Error::Builder(format!("request could not be serialized: {error}")))
+}
+
+/// Sets the key of the default client, used by all OpenAI API functions that are not given an [`OpenAiClient`].
 ///
 /// ## Examples
 ///
@@ -99,5 +772,315 @@ where
 /// set_key(env::var("OPENAI_KEY").unwrap());
 /// ```
 pub fn set_key(value: String) {
-    *API_KEY.lock().unwrap() = value;
+    let mut default_client = DEFAULT_CLIENT.lock().unwrap();
+
+    match &mut *default_client {
+        Some(client) => client.key = value,
+        None => *default_client = Some(OpenAiClient::new(value)),
+    }
+}
+
+/// Sets the organization of the default client, overriding the `OPENAI_ORG_ID` environment variable.
+pub fn set_organization(value: String) {
+    DEFAULT_CLIENT
+        .lock()
+        .unwrap()
+        .get_or_insert_with(|| OpenAiClient::new(""))
+        .org = Some(value);
+}
+
+/// Sets the project of the default client, overriding the `OPENAI_PROJECT_ID` environment variable.
+pub fn set_project(value: String) {
+    DEFAULT_CLIENT
+        .lock()
+        .unwrap()
+        .get_or_insert_with(|| OpenAiClient::new(""))
+        .project = Some(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chat::{
+            ChatCompletion, ChatCompletionBuilder, ChatCompletionMessage, ChatCompletionMessageRole,
+        },
+        embeddings::Embeddings,
+        models::Model,
+        moderations::Moderation,
+        stub::{StubResponse, StubServer},
+    };
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn clients() {
+        let server = StubServer::start(vec![StubResponse::json(
+            200,
+            json!({
+                "id": "text-davinci-003",
+                "created": 1669599635,
+                "owned_by": "openai-internal",
+                "permission": [],
+                "root": "text-davinci-003",
+                "parent": null,
+            }),
+        )])
+        .await;
+        let first = OpenAiClient::with_env("first", |_| None).base_url(server.url());
+        let second = OpenAiClient::with_env("second", |_| None)
+            .base_url(server.url())
+            .organization("org-123")
+            .project("proj_123");
+
+        Model::from_with_client("text-davinci-003", &first)
+            .await
+            .unwrap();
+        Model::from_with_client("text-davinci-003", &second)
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+
+        assert_eq!(requests[0].headers["authorization"], "Bearer first");
+        assert!(!requests[0].headers.contains_key("openai-organization"));
+        assert_eq!(requests[1].headers["authorization"], "Bearer second");
+        assert_eq!(requests[1].headers["openai-organization"], "org-123");
+        assert!(!requests[0].headers.contains_key("openai-project"));
+        assert_eq!(requests[1].headers["openai-project"], "proj_123");
+    }
+
+    #[tokio::test]
+    async fn organizations() {
+        let server = StubServer::start(vec![StubResponse::json(
+            200,
+            json!({
+                "id": "modr-5MWoLO",
+                "model": "text-moderation-001",
+                "results": [],
+            }),
+        )])
+        .await;
+
+        let client = OpenAiClient::with_env("", |name| match name {
+            "OPENAI_ORG_ID" => Some("org-env".to_string()),
+            "OPENAI_PROJECT_ID" => Some("proj_env".to_string()),
+            _ => None,
+        })
+        .base_url(server.url());
+
+        Moderation::builder("Hello!")
+            .client(&client)
+            .create()
+            .await
+            .unwrap();
+        Moderation::builder("Hello!")
+            .client(&client)
+            .organization("org-request")
+            .create()
+            .await
+            .unwrap();
+        Moderation::builder("Hello!")
+            .client(client.organization("org-client"))
+            .project("proj_request")
+            .create()
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+
+        assert_eq!(requests[0].headers["openai-organization"], "org-env");
+        assert_eq!(requests[0].headers["openai-project"], "proj_env");
+        assert_eq!(requests[1].headers["openai-organization"], "org-request");
+        assert_eq!(requests[1].headers["openai-project"], "proj_env");
+        assert_eq!(requests[2].headers["openai-organization"], "org-client");
+        assert_eq!(requests[2].headers["openai-project"], "proj_request");
+    }
+
+    #[test]
+    fn base_url() {
+        let client = OpenAiClient::with_env("", |_| None).base_url("http://localhost:8080/v1");
+
+        assert_eq!(
+            client.url("chat/completions", None),
+            "http://localhost:8080/v1/chat/completions"
+        );
+
+        let client = client.base_url("http://localhost:8080/v1/");
+
+        assert_eq!(
+            client.url("/chat/completions", None),
+            "http://localhost:8080/v1/chat/completions"
+        );
+
+        let client = OpenAiClient::with_env("", |name| {
+            (name == "OPENAI_BASE_URL").then(|| "http://localhost:8080/openai/".to_string())
+        });
+
+        assert_eq!(
+            client.url("models", None),
+            "http://localhost:8080/openai/models"
+        );
+        assert_eq!(
+            OpenAiClient::with_env("", |_| None).url("models", None),
+            BASE_URL.to_owned() + "models"
+        );
+    }
+
+    #[tokio::test]
+    async fn azure() {
+        let server = StubServer::start(vec![
+            StubResponse::json(
+                200,
+                json!({
+                    "id": "chatcmpl-123",
+                    "object": "chat.completion",
+                    "created": 1677652288,
+                    "model": "gpt-35-turbo",
+                    "prompt_filter_results": [],
+                    "choices": [{
+                        "index": 0,
+                        "message": {"role": "assistant", "content": "Hello there!"},
+                        "finish_reason": "stop",
+                    }],
+                    "usage": {"prompt_tokens": 9, "completion_tokens": 3, "total_tokens": 12},
+                }),
+            ),
+            StubResponse::json(
+                200,
+                json!({
+                    "object": "list",
+                    "data": [{"object": "embedding", "embedding": [0.1, 0.2], "index": 0}],
+                    "model": "ada",
+                    "usage": {"prompt_tokens": 4, "total_tokens": 4},
+                }),
+            ),
+            StubResponse::json(
+                400,
+                json!({
+                    "error": {
+                        "message": "The response was filtered due to the prompt triggering Azure OpenAI's content management policy.",
+                        "type": null,
+                        "param": "prompt",
+                        "code": "content_filter",
+                        "status": 400,
+                        "innererror": {
+                            "code": "ResponsibleAIPolicyViolation",
+                            "content_filter_result": {
+                                "hate": {"filtered": false, "severity": "safe"},
+                                "self_harm": {"filtered": false, "severity": "safe"},
+                                "sexual": {"filtered": false, "severity": "safe"},
+                                "violence": {"filtered": true, "severity": "medium"},
+                            },
+                        },
+                    },
+                }),
+            ),
+        ])
+        .await;
+        let client = OpenAiClient::azure(server.url(), "azure-key", "2023-05-15")
+            .deployment("gpt-3.5-turbo", "my-gpt");
+        let messages = [ChatCompletionMessage::new(
+            ChatCompletionMessageRole::User,
+            "Hello!",
+        )];
+
+        ChatCompletion::builder("gpt-3.5-turbo", messages.clone())
+            .client(&client)
+            .create()
+            .await
+            .unwrap();
+        Embeddings::create_with_client("text-embedding-ada-002", vec!["Hello!"], "", &client)
+            .await
+            .unwrap();
+
+        let error = match ChatCompletion::builder("gpt-3.5-turbo", messages)
+            .client(&client)
+            .create()
+            .await
+        {
+            Err(Error::Api { status, error }) => {
+                assert_eq!(status, StatusCode::BAD_REQUEST);
+                error
+            }
+            _ => panic!("expected an API error"),
+        };
+        let requests = server.requests();
+
+        assert_eq!(
+            requests[0].path,
+            "/v1/openai/deployments/my-gpt/chat/completions?api-version=2023-05-15"
+        );
+        assert_eq!(requests[0].headers["api-key"], "azure-key");
+        assert!(!requests[0].headers.contains_key("authorization"));
+        assert_eq!(
+            requests[1].path,
+            "/v1/openai/deployments/text-embedding-ada-002/embeddings?api-version=2023-05-15"
+        );
+        assert_eq!(error.code.as_deref(), Some("content_filter"));
+        assert_eq!(error.error_type, "");
+
+        let content_filter_result = error.inner_error.unwrap().content_filter_result.unwrap();
+
+        assert!(content_filter_result.violence.unwrap().filtered);
+        assert!(!content_filter_result.hate.unwrap().filtered);
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let server = StubServer::start(vec![
+            StubResponse::json(
+                401,
+                json!({
+                    "error": {
+                        "message": "Incorrect API key provided: sk-1234.",
+                        "type": "invalid_request_error",
+                        "param": null,
+                        "code": "invalid_api_key",
+                    },
+                }),
+            ),
+            StubResponse::text(502, "text/html", "<html><h1>502 Bad Gateway</h1></html>"),
+        ])
+        .await;
+        let client = OpenAiClient::with_env("sk-1234", |_| None).base_url(server.url());
+
+        match Model::from_with_client("text-davinci-003", &client).await {
+            Err(Error::Api { status, error }) => {
+                assert_eq!(status, StatusCode::UNAUTHORIZED);
+                assert_eq!(error.error_type, "invalid_request_error");
+                assert_eq!(error.code.as_deref(), Some("invalid_api_key"));
+            }
+            _ => panic!("expected an API error"),
+        }
+
+        match Model::from_with_client("text-davinci-003", &client).await {
+            Err(Error::Decode { status, body, .. }) => {
+                assert_eq!(status, StatusCode::BAD_GATEWAY);
+                assert!(body.contains("502 Bad Gateway"));
+            }
+            _ => panic!("expected a decode error"),
+        }
+
+        assert!(matches!(
+            ChatCompletionBuilder::default()
+                .client(&client)
+                .create()
+                .await,
+            Err(Error::Builder(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = OpenAiClient::with_env("", |_| None)
+            .base_url(format!("http://{}/v1/", listener.local_addr().unwrap()))
+            .timeout(Duration::from_millis(50));
+
+        assert!(matches!(
+            Model::from_with_client("text-davinci-003", &client).await,
+            Err(Error::Timeout(_))
+        ));
+    }
 }
@@ -0,0 +1,134 @@
+use serde::{ser::Error as _, Serialize, Serializer};
+use std::collections::BTreeMap;
+
+/// Changes how likely tokens are to appear in a completion, see `logit_bias` on
+/// [`ChatCompletionBuilder`](crate::chat::ChatCompletionBuilder) and [`CompletionBuilder`](crate::completions::CompletionBuilder).
+///
+/// Every token is mapped to a bias from -100 to 100, which is added to the logits generated by the model prior to sampling.
+/// Values between -1 and 1 should decrease or increase the likelihood of selection;
+/// values like -100 or 100 should result in a ban or exclusive selection of the token.
+/// Requests with a bias outside of this range fail before they are sent.
+///
+/// ## Examples
+///
+/// ```rust
+/// use openai::LogitBias;
+///
+/// // Prevents the <|endoftext|> token of GPT-3 models from being generated
+/// let logit_bias = LogitBias::new().token(50256, -100.0);
+///
+/// assert_eq!(logit_bias.get(50256), Some(-100.0));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LogitBias {
+    biases: BTreeMap<u32, f32>,
+}
+
+impl LogitBias {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the bias of a token, specified by its ID in the tokenizer of the model.
+    pub fn token(mut self, token: u32, bias: f32) -> Self {
+        self.biases.insert(token, bias);
+        self
+    }
+
+    /// Sets the bias of every token of `word`, encoded with `encoding`.
+    ///
+    /// The tokens of the word preceded by a space are biased as well,
+    /// since that is how words appear inside of sentences.
+    /// Words made of several tokens bias each of them, which affects other words sharing those tokens.
+    #[cfg(feature = "tokenizer")]
+    pub fn word(mut self, encoding: crate::tokenizer::Encoding, word: &str, bias: f32) -> Self {
+        let word = word.trim();
+
+        for token in encoding
+            .encode(word)
+            .into_iter()
+            .chain(encoding.encode(&format!(" {word}")))
+        {
+            self.biases.insert(token, bias);
+        }
+
+        self
+    }
+
+    /// Returns the bias of a token, if it was set.
+    pub fn get(&self, token: u32) -> Option<f32> {
+        self.biases.get(&token).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.biases.is_empty()
+    }
+
+    /// Returns the tokens and their biases, ordered by token.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, f32)> + '_ {
+        self.biases.iter().map(|(token, bias)| (*token, *bias))
+    }
+
+    /// Returns the first token with a bias outside of -100 to 100, and its bias.
+    pub(crate) fn out_of_range(&self) -> Option<(u32, f32)> {
+        self.iter()
+            .find(|(_, bias)| !(-100.0..=100.0).contains(bias))
+    }
+}
+
+impl FromIterator<(u32, f32)> for LogitBias {
+    fn from_iter<I: IntoIterator<Item = (u32, f32)>>(iter: I) -> Self {
+        LogitBias {
+            biases: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<const N: usize> From<[(u32, f32); N]> for LogitBias {
+    fn from(biases: [(u32, f32); N]) -> Self {
+        biases.into_iter().collect()
+    }
+}
+
+impl Serialize for LogitBias {
+    /// Serializes the biases as an object, with token IDs as keys.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if let Some((token, bias)) = self.out_of_range() {
+            return Err(S::Error::custom(format!(
+                "the bias of token {token} is {bias}, which is outside of -100 to 100"
+            )));
+        }
+
+        serializer.collect_map(&self.biases)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn serialization() {
+        let logit_bias = LogitBias::from([(50256, -100.0), (198, 1.5)]);
+
+        assert_eq!(
+            serde_json::to_value(&logit_bias).unwrap(),
+            json!({ "198": 1.5, "50256": -100.0 })
+        );
+        assert!(serde_json::to_value(logit_bias.token(1, 101.0)).is_err());
+        assert!(serde_json::to_value(LogitBias::new().token(1, f32::NAN)).is_err());
+    }
+
+    #[cfg(feature = "tokenizer")]
+    #[test]
+    fn words() {
+        use crate::tokenizer::Encoding;
+
+        let logit_bias = LogitBias::new().word(Encoding::Cl100kBase, "hello", -50.0);
+
+        assert_eq!(logit_bias.get(15339), Some(-50.0));
+        assert_eq!(logit_bias.get(24748), Some(-50.0));
+        assert_eq!(logit_bias.iter().count(), 2);
+    }
+}
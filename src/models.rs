@@ -2,7 +2,7 @@
 //! You can refer to the [Models](https://beta.openai.com/docs/models)
 //! documentation to understand what models are available and the differences between them.
 
-use super::{openai_get, ApiResponseOrError};
+use super::{openai_get, Error, OpenAiClient, Response};
 use serde::Deserialize;
 
 #[derive(Deserialize, Clone)]
@@ -33,16 +33,29 @@ pub struct ModelPermission {
 impl Model {
     //! Retrieves a model instance,
     //! providing basic information about the model such as the owner and permissioning.
-    pub async fn from(id: &str) -> ApiResponseOrError<Self> {
-        openai_get(&format!("models/{id}")).await
+    pub async fn from(id: &str) -> Result<Self, Error> {
+        openai_get(None, &format!("models/{id}"))
+            .await
+            .map(Response::into_body)
+    }
+
+    /// Like [`Model::from`], but the request is made with `client`.
+    pub async fn from_with_client(id: &str, client: &OpenAiClient) -> Result<Self, Error> {
+        openai_get(Some(client), &format!("models/{id}"))
+            .await
+            .map(Response::into_body)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::set_key;
+    use crate::{
+        set_key,
+        stub::{StubResponse, StubServer},
+    };
     use dotenvy::dotenv;
+    use serde_json::json;
     use std::env;
 
     #[tokio::test]
@@ -50,7 +63,7 @@ mod tests {
         dotenv().ok();
         set_key(env::var("OPENAI_KEY").unwrap());
 
-        let model = Model::from("text-davinci-003").await.unwrap().unwrap();
+        let model = Model::from("text-davinci-003").await.unwrap();
 
         assert_eq!(model.id, "text-davinci-003");
     }
@@ -62,9 +75,35 @@ mod tests {
 
         let model = Model::from("davinci:ft-personal-2022-12-12-04-49-51")
             .await
-            .unwrap()
             .unwrap();
 
         assert_eq!(model.id, "davinci:ft-personal-2022-12-12-04-49-51");
     }
+
+    #[tokio::test]
+    async fn model_stub() {
+        let server = StubServer::start(vec![StubResponse::json(
+            200,
+            json!({
+                "id": "text-davinci-003",
+                "created": 1669599635,
+                "owned_by": "openai-internal",
+                "permission": [],
+                "root": "text-davinci-003",
+                "parent": null,
+            }),
+        )])
+        .await;
+        let client = OpenAiClient::new("key").base_url(server.url());
+        let model = Model::from_with_client("text-davinci-003", &client)
+            .await
+            .unwrap();
+
+        assert_eq!(model.id, "text-davinci-003");
+
+        let request = &server.requests()[0];
+
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/v1/models/text-davinci-003");
+    }
 }
@@ -1,6 +1,8 @@
 //! Given a input text, outputs if the model classifies it as violating OpenAI's content policy.
 
-use super::{openai_post, ApiResponseOrError};
+use super::{
+    openai_post, request_client, Error, OpenAiClient, Response, Validate, ValidationError,
+};
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 
@@ -52,6 +54,7 @@ pub struct CategoryScores {
 #[builder(pattern = "owned")]
 #[builder(name = "ModerationBuilder")]
 #[builder(setter(strip_option, into))]
+#[builder(build_fn(error = "Error"))]
 pub struct ModerationRequest {
     /// The input text to classify.
     pub input: String,
@@ -60,11 +63,42 @@ pub struct ModerationRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[builder(default)]
     pub model: Option<String>,
+    /// The client to make the request with.
+    /// Defaults to the client configured with [`set_key`](crate::set_key).
+    #[serde(skip)]
+    #[builder(default)]
+    pub client: Option<OpenAiClient>,
+    /// The organization the request counts towards, instead of that of its client.
+    #[serde(skip)]
+    #[builder(default)]
+    pub organization: Option<String>,
+    /// The project the request counts towards, instead of that of its client.
+    #[serde(skip)]
+    #[builder(default)]
+    pub project: Option<String>,
+}
+
+impl Validate for ModerationRequest {
+    fn validate(&self) -> Result<(), ValidationError> {
+        if self.input.is_empty() {
+            return Err(ValidationError::Empty { parameter: "input" });
+        }
+
+        Ok(())
+    }
 }
 
 impl Moderation {
-    async fn create(request: &ModerationRequest) -> ApiResponseOrError<Self> {
-        openai_post("moderations", request).await
+    async fn create(request: &ModerationRequest) -> Result<Response<Self>, Error> {
+        request.validate()?;
+
+        let client = request_client(
+            request.client.as_ref(),
+            request.organization.as_ref(),
+            request.project.as_ref(),
+        );
+
+        openai_post(client.as_ref(), "moderations", request).await
     }
 
     pub fn builder(input: impl Into<String>) -> ModerationBuilder {
@@ -73,16 +107,25 @@ impl Moderation {
 }
 
 impl ModerationBuilder {
-    pub async fn create(self) -> ApiResponseOrError<Moderation> {
-        Moderation::create(&self.build().unwrap()).await
+    pub async fn create(self) -> Result<Moderation, Error> {
+        Ok(self.create_with_metadata().await?.body)
+    }
+
+    /// Like [`ModerationBuilder::create`], but the metadata of the response is returned too.
+    pub async fn create_with_metadata(self) -> Result<Response<Moderation>, Error> {
+        Moderation::create(&self.build()?).await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::set_key;
+    use crate::{
+        set_key,
+        stub::{StubResponse, StubServer},
+    };
     use dotenvy::dotenv;
+    use serde_json::json;
     use std::env;
 
     #[tokio::test]
@@ -94,13 +137,57 @@ mod tests {
             .model("text-moderation-latest")
             .create()
             .await
-            .unwrap()
             .unwrap();
 
-        assert_eq!(
-            moderation.results.first().unwrap().categories.violence,
-            true
-        );
-        assert_eq!(moderation.results.first().unwrap().flagged, true);
+        assert!(moderation.results.first().unwrap().categories.violence);
+        assert!(moderation.results.first().unwrap().flagged);
+    }
+
+    #[tokio::test]
+    async fn moderations_stub() {
+        let categories = json!({
+            "hate": false,
+            "hate/threatening": false,
+            "self-harm": false,
+            "sexual": false,
+            "sexual/minors": false,
+            "violence": true,
+            "violence/graphic": false,
+        });
+        let category_scores = json!({
+            "hate": 0.18,
+            "hate/threatening": 0.0031,
+            "self-harm": 0.000000015,
+            "sexual": 0.0000092,
+            "sexual/minors": 0.000000013,
+            "violence": 0.93,
+            "violence/graphic": 0.0000044,
+        });
+        let server = StubServer::start(vec![StubResponse::json(
+            200,
+            json!({
+                "id": "modr-5MWoLO",
+                "model": "text-moderation-004",
+                "results": [{
+                    "flagged": true,
+                    "categories": categories,
+                    "category_scores": category_scores,
+                }],
+            }),
+        )])
+        .await;
+        let client = OpenAiClient::new("key").base_url(server.url());
+        let moderation = Moderation::builder("I want to kill them.")
+            .client(&client)
+            .create()
+            .await
+            .unwrap();
+
+        assert!(moderation.results.first().unwrap().categories.violence);
+
+        let request = &server.requests()[0];
+
+        assert_eq!(request.path, "/v1/moderations");
+        assert_eq!(request.json(), json!({"input": "I want to kill them."}));
     }
 }
@@ -0,0 +1,364 @@
+use reqwest::header::HeaderMap;
+use serde_json::Value;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Fields of request bodies whose text counts towards the tokens of a request.
+const PROMPT_FIELDS: [&str; 5] = ["prompt", "messages", "input", "instruction", "suffix"];
+
+/// Keeps requests within the request and token rate limits of an organization,
+/// so that concurrent requests queue up instead of being rejected with a `429` status.
+///
+/// The limits are learned from the `x-ratelimit-*` headers of the API's responses,
+/// so until a first response arrives, requests are only limited by what was given to [`RateLimiter::with_limits`].
+/// The tokens of a request are estimated from the length of its prompt and its `max_tokens`.
+///
+/// Clones share their limits, so one limiter can be given to every client of an organization.
+///
+/// ## Examples
+///
+/// ```rust
+/// use openai::{OpenAiClient, RateLimiter};
+///
+/// let limiter = RateLimiter::with_limits(3_500, 90_000);
+/// let client = OpenAiClient::new("sk-...").rate_limiter(limiter.clone());
+/// let other_client = OpenAiClient::new("sk-...").rate_limiter(limiter);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct RateLimiter {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Held while waiting for capacity, so that requests are sent in the order they were made.
+    queue: tokio::sync::Mutex<()>,
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    available: f64,
+    refill_per_second: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a limiter that learns its limits from the API's responses.
+    pub fn new() -> Self {
+        RateLimiter::default()
+    }
+
+    /// Creates a limiter starting with limits of `requests_per_minute` and `tokens_per_minute`,
+    /// which are then updated by the API's responses.
+    pub fn with_limits(requests_per_minute: u32, tokens_per_minute: u32) -> Self {
+        let limiter = RateLimiter::new();
+
+        {
+            let mut buckets = limiter.inner.buckets.lock().unwrap();
+
+            buckets.requests = Some(Bucket::new(requests_per_minute as f64));
+            buckets.tokens = Some(Bucket::new(tokens_per_minute as f64));
+        }
+
+        limiter
+    }
+
+    /// Waits until a request costing `tokens` can be sent, and counts it against the limits.
+    pub(crate) async fn acquire(&self, tokens: u32) {
+        let _turn = self.inner.queue.lock().await;
+
+        loop {
+            let wait = match self.inner.buckets.lock().unwrap().take(tokens as f64) {
+                Ok(()) => return,
+                Err(wait) => wait,
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Updates the limits from the `x-ratelimit-*` headers of a response.
+    pub(crate) fn update(&self, headers: &HeaderMap) {
+        let mut buckets = self.inner.buckets.lock().unwrap();
+        let Buckets { requests, tokens } = &mut *buckets;
+
+        for (kind, bucket) in [("requests", requests), ("tokens", tokens)] {
+            let rate_limit = RateLimit::from_headers(headers, kind);
+            let Some(limit) = rate_limit.limit.map(|limit| limit as f64) else {
+                continue;
+            };
+
+            bucket.get_or_insert_with(|| Bucket::new(limit)).observe(
+                limit,
+                rate_limit.remaining.map(|remaining| remaining as f64),
+                rate_limit.reset,
+            );
+        }
+    }
+}
+
+/// One of the rate limits of an organization, as reported by the `x-ratelimit-*` headers of a response.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    /// How many requests or tokens are allowed per minute.
+    pub limit: Option<u64>,
+    /// How many requests or tokens are left before the limit is reached.
+    pub remaining: Option<u64>,
+    /// How long until [`RateLimit::remaining`] is back to [`RateLimit::limit`].
+    pub reset: Option<Duration>,
+}
+
+impl RateLimit {
+    /// Reads the rate limit of `kind`, `requests` or `tokens`, from `headers`.
+    pub(crate) fn from_headers(headers: &HeaderMap, kind: &str) -> Self {
+        let header = |name: &str| {
+            headers
+                .get(format!("x-ratelimit-{name}-{kind}"))
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+        };
+
+        RateLimit {
+            limit: header("limit").and_then(|limit| limit.parse().ok()),
+            remaining: header("remaining").and_then(|remaining| remaining.parse().ok()),
+            reset: header("reset").and_then(parse_duration),
+        }
+    }
+}
+
+impl Buckets {
+    /// Takes one request and `tokens` from the buckets,
+    /// or returns how long to wait until there is enough of both.
+    fn take(&mut self, tokens: f64) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut wait = Duration::ZERO;
+
+        for (bucket, amount) in [(&mut self.requests, 1.0), (&mut self.tokens, tokens)] {
+            if let Some(bucket) = bucket {
+                bucket.refill(now);
+                wait = wait.max(bucket.wait(amount));
+            }
+        }
+
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        for (bucket, amount) in [(&mut self.requests, 1.0), (&mut self.tokens, tokens)] {
+            if let Some(bucket) = bucket {
+                bucket.available -= amount.min(bucket.capacity);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Bucket {
+    fn new(capacity: f64) -> Self {
+        Bucket {
+            capacity,
+            available: capacity,
+            refill_per_second: capacity / 60.0,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+
+        self.available = (self.available + elapsed * self.refill_per_second).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Returns how long to wait until `amount` is available.
+    fn wait(&self, amount: f64) -> Duration {
+        // Requests bigger than the whole bucket only wait for it to be full
+        let missing = amount.min(self.capacity) - self.available;
+
+        if missing <= 0.0 {
+            return Duration::ZERO;
+        }
+
+        Duration::try_from_secs_f64(missing / self.refill_per_second)
+            .unwrap_or(Duration::from_secs(60))
+    }
+
+    /// Adjusts the bucket to what the API reported,
+    /// `remaining` out of `limit` being left until the limit fully `reset`s.
+    fn observe(&mut self, limit: f64, remaining: Option<f64>, reset: Option<Duration>) {
+        self.refill(Instant::now());
+        self.capacity = limit;
+        self.refill_per_second = match (remaining, reset) {
+            (Some(remaining), Some(reset)) if limit > remaining && !reset.is_zero() => {
+                (limit - remaining) / reset.as_secs_f64()
+            }
+            _ => limit / 60.0,
+        };
+        // Requests still in flight were counted here but not yet by the API, so keep the lower count
+        self.available = match remaining {
+            Some(remaining) => self.available.min(remaining),
+            None => self.available.min(limit),
+        };
+    }
+}
+
+/// Estimates how many tokens a request with `body` counts against the token rate limit.
+///
+/// Like the API, this counts about 4 characters of prompt per token,
+/// plus the most tokens that may be generated.
+pub(crate) fn estimate_tokens(body: &Value) -> u32 {
+    let characters: usize = PROMPT_FIELDS
+        .iter()
+        .filter_map(|field| body.get(field))
+        .map(text_length)
+        .sum();
+    let max_tokens = body.get("max_tokens").and_then(Value::as_u64).unwrap_or(0);
+    let n = body.get("n").and_then(Value::as_u64).unwrap_or(1);
+    let tokens = (characters as u64 / 4).saturating_add(max_tokens.saturating_mul(n));
+
+    tokens.try_into().unwrap_or(u32::MAX)
+}
+
+fn text_length(value: &Value) -> usize {
+    match value {
+        Value::String(text) => text.chars().count(),
+        Value::Array(values) => values.iter().map(text_length).sum(),
+        Value::Object(fields) => fields.values().map(text_length).sum(),
+        _ => 0,
+    }
+}
+
+/// Parses durations in the format of the `x-ratelimit-reset-*` headers, such as `6m0s`, `1.5s` or `20ms`.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let is_number = |c: char| c.is_ascii_digit() || c == '.';
+    let mut rest = value.trim();
+    let mut seconds = 0.0;
+
+    if rest.is_empty() {
+        return None;
+    }
+
+    while !rest.is_empty() {
+        let number_end = rest.find(|c| !is_number(c)).unwrap_or(rest.len());
+        let number: f64 = rest[..number_end].parse().ok()?;
+
+        rest = &rest[number_end..];
+
+        let unit_end = rest.find(is_number).unwrap_or(rest.len());
+        let unit = match &rest[..unit_end] {
+            "ms" => 0.001,
+            "s" | "" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            "d" => 86400.0,
+            _ => return None,
+        };
+
+        seconds += number * unit;
+        rest = &rest[unit_end..];
+    }
+
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::Model,
+        stub::{StubResponse, StubServer},
+        OpenAiClient,
+    };
+    use serde_json::json;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("20ms"), Some(Duration::from_millis(20)));
+        assert_eq!(parse_duration("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(parse_duration("1h2m3s"), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_duration("3"), Some(Duration::from_secs(3)));
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("soon"), None);
+    }
+
+    #[test]
+    fn token_estimates() {
+        let body = json!({
+            "model": "gpt-3.5-turbo",
+            "messages": [{ "role": "user", "content": "Hello!" }],
+            "max_tokens": 100,
+            "n": 2,
+        });
+
+        // "user" and "Hello!" are 10 characters
+        assert_eq!(estimate_tokens(&body), 202);
+        assert_eq!(estimate_tokens(&json!({ "prompt": "a".repeat(40) })), 10);
+    }
+
+    #[tokio::test]
+    async fn waits_for_capacity() {
+        let limiter = RateLimiter::new();
+        let mut headers = HeaderMap::new();
+
+        limiter.acquire(1_000_000).await;
+
+        headers.insert("x-ratelimit-limit-requests", "10".parse().unwrap());
+        headers.insert("x-ratelimit-remaining-requests", "0".parse().unwrap());
+        headers.insert("x-ratelimit-reset-requests", "1s".parse().unwrap());
+        limiter.update(&headers);
+
+        let start = Instant::now();
+
+        limiter.acquire(1_000_000).await;
+
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[tokio::test]
+    async fn queues_concurrent_requests() {
+        let model = json!({
+            "id": "text-davinci-003",
+            "created": 1669599635,
+            "owned_by": "openai-internal",
+            "permission": [],
+            "root": "text-davinci-003",
+            "parent": null,
+        });
+        let server = StubServer::start(vec![StubResponse::json(200, model)
+            .header("x-ratelimit-limit-requests", "10")
+            .header("x-ratelimit-remaining-requests", "0")
+            .header("x-ratelimit-reset-requests", "1s")])
+        .await;
+        let client = OpenAiClient::new("")
+            .base_url(server.url())
+            .rate_limiter(RateLimiter::new());
+
+        Model::from_with_client("text-davinci-003", &client)
+            .await
+            .unwrap();
+
+        let start = Instant::now();
+        let (first, second, third) = tokio::join!(
+            Model::from_with_client("text-davinci-003", &client),
+            Model::from_with_client("text-davinci-003", &client),
+            Model::from_with_client("text-davinci-003", &client),
+        );
+
+        assert!(first.is_ok() && second.is_ok() && third.is_ok());
+        // Every response leaves no requests, which come back at 10 per second
+        assert!(start.elapsed() >= Duration::from_millis(250));
+        assert_eq!(server.requests().len(), 4);
+    }
+}
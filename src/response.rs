@@ -0,0 +1,170 @@
+use super::RateLimit;
+use reqwest::{header::HeaderMap, StatusCode};
+use std::{ops::Deref, time::Duration};
+
+/// The body of a response of the API, along with the status and metadata of the HTTP response.
+///
+/// Dereferences to the body.
+///
+/// ## Examples
+///
+/// ```rust,no_run
+/// use openai::{chat::{ChatCompletion, ChatCompletionMessage, ChatCompletionMessageRole}, set_key};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), openai::Error> {
+/// set_key("sk-...".to_string());
+///
+/// let message = ChatCompletionMessage::new(ChatCompletionMessageRole::User, "Hello!");
+/// let response = ChatCompletion::builder("gpt-3.5-turbo", [message])
+///     .create_with_metadata()
+///     .await?;
+///
+/// println!("{:?} took {:?}", response.metadata.request_id, response.metadata.processing_time);
+/// println!("{:?}", response.choices[0].message.content);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Response<T> {
+    pub body: T,
+    pub status: StatusCode,
+    pub metadata: ResponseMetadata,
+}
+
+impl<T> Response<T> {
+    pub fn into_body(self) -> T {
+        self.body
+    }
+
+    /// Transforms the body, keeping the status and metadata.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Response<U> {
+        Response {
+            body: f(self.body),
+            status: self.status,
+            metadata: self.metadata,
+        }
+    }
+}
+
+impl<T> Deref for Response<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.body
+    }
+}
+
+/// What the headers of a response tell about how a request was handled.
+#[derive(Clone, Debug, Default)]
+pub struct ResponseMetadata {
+    /// The `x-request-id` header, which identifies the request to OpenAI support.
+    pub request_id: Option<String>,
+    /// The `openai-processing-ms` header, how long the API took to handle the request.
+    pub processing_time: Option<Duration>,
+    /// The `openai-organization` header, the organization the request was billed to.
+    pub organization: Option<String>,
+    /// The `x-ratelimit-*-requests` headers.
+    pub requests: RateLimit,
+    /// The `x-ratelimit-*-tokens` headers.
+    pub tokens: RateLimit,
+    /// Every header of the response.
+    pub headers: HeaderMap,
+}
+
+impl ResponseMetadata {
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().to_string())
+        };
+
+        ResponseMetadata {
+            request_id: header("x-request-id"),
+            processing_time: header("openai-processing-ms")
+                .and_then(|milliseconds| milliseconds.parse::<f64>().ok())
+                .and_then(|milliseconds| Duration::try_from_secs_f64(milliseconds / 1000.0).ok()),
+            organization: header("openai-organization"),
+            requests: RateLimit::from_headers(headers, "requests"),
+            tokens: RateLimit::from_headers(headers, "tokens"),
+            headers: headers.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        moderations::Moderation,
+        stub::{StubResponse, StubServer},
+        OpenAiClient, RateLimit,
+    };
+    use reqwest::StatusCode;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn metadata() {
+        let moderation = json!({
+            "id": "modr-5MWoLO",
+            "model": "text-moderation-001",
+            "results": [{
+                "categories": {
+                    "hate": false,
+                    "hate/threatening": false,
+                    "self-harm": false,
+                    "sexual": false,
+                    "sexual/minors": false,
+                    "violence": false,
+                    "violence/graphic": false,
+                },
+                "category_scores": {
+                    "hate": 0.0,
+                    "hate/threatening": 0.0,
+                    "self-harm": 0.0,
+                    "sexual": 0.0,
+                    "sexual/minors": 0.0,
+                    "violence": 0.0,
+                    "violence/graphic": 0.0,
+                },
+                "flagged": false,
+            }],
+        });
+        let server = StubServer::start(vec![StubResponse::json(200, moderation)
+            .header("x-request-id", "req_123")
+            .header("openai-processing-ms", "42")
+            .header("openai-organization", "acme")
+            .header("x-ratelimit-limit-requests", "3500")
+            .header("x-ratelimit-remaining-requests", "3499")
+            .header("x-ratelimit-reset-requests", "17ms")
+            .header("x-ratelimit-limit-tokens", "90000")])
+        .await;
+        let client = OpenAiClient::with_env("", |_| None).base_url(server.url());
+        let response = Moderation::builder("I want to kill them.")
+            .client(&client)
+            .create_with_metadata()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.metadata.request_id.as_deref(), Some("req_123"));
+        assert_eq!(
+            response.metadata.processing_time,
+            Some(Duration::from_millis(42))
+        );
+        assert_eq!(response.metadata.organization.as_deref(), Some("acme"));
+        assert_eq!(
+            response.metadata.requests,
+            RateLimit {
+                limit: Some(3500),
+                remaining: Some(3499),
+                reset: Some(Duration::from_millis(17)),
+            }
+        );
+        assert_eq!(response.metadata.tokens.limit, Some(90000));
+        assert_eq!(response.metadata.tokens.remaining, None);
+        assert!(!response.results[0].flagged);
+    }
+}
@@ -0,0 +1,268 @@
+use super::Error;
+use reqwest::{header::HeaderMap, Method, StatusCode};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+/// Waits suggested by the API for longer than this are ignored in favor of the policy's own backoff.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// How requests that fail for a transient reason,
+/// such as a rate limit or an overloaded server, are retried.
+///
+/// Requests are retried when the API responds with a `408`, `409`, `429` or `5xx` status,
+/// except for `429`s caused by an exhausted quota, or when a connection could not be established.
+/// Requests that may already have been processed, because they timed out or lost their connection,
+/// are only retried if they are idempotent.
+///
+/// Between attempts, the wait suggested by the `retry-after-ms` or `retry-after` header is honored.
+/// Without one, the wait doubles after every attempt, with some jitter.
+///
+/// ## Examples
+///
+/// ```rust
+/// use openai::{OpenAiClient, RetryPolicy};
+/// use std::time::Duration;
+///
+/// let client = OpenAiClient::new("sk-...")
+///     .retry_policy(RetryPolicy::new(5).max_backoff(Duration::from_secs(30)));
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Attempts requests up to `max_attempts` times in total.
+    /// The first retry waits about half a second, and no wait is longer than 8 seconds.
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+        }
+    }
+
+    /// Never retries requests. This is the policy of clients that have not been given one.
+    pub fn never() -> Self {
+        RetryPolicy::new(1)
+    }
+
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub(crate) fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Returns how long to wait before retrying a request that has been attempted `attempt` times.
+    pub(crate) fn backoff(&self, attempt: u32, headers: Option<&HeaderMap>) -> Duration {
+        if let Some(retry_after) = headers.and_then(retry_after) {
+            return retry_after;
+        }
+
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff);
+
+        // Wait somewhere between half and all of the backoff,
+        // so that clients that failed together don't all retry together.
+        backoff.mul_f64(0.5 + random_fraction() / 2.0)
+    }
+
+    /// Returns whether a request made with `method` that failed with `error` is worth retrying.
+    pub(crate) fn should_retry(&self, method: &Method, error: &Error) -> bool {
+        match error {
+            Error::Api { status, error } => {
+                retryable_status(*status) && error.code.as_deref() != Some("insufficient_quota")
+            }
+            Error::Decode { status, .. } => retryable_status(*status),
+            Error::Transport(error) if error.is_connect() => true,
+            Error::Transport(_) | Error::Timeout(_) => method.is_idempotent(),
+            Error::Builder(_)
+            | Error::Validation(_)
+            | Error::Refusal(_)
+            | Error::InvalidOutput { .. }
+            | Error::BudgetExceeded { .. }
+            | Error::DimensionMismatch { .. }
+            | Error::IncompleteResponse(_) => false,
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::never()
+    }
+}
+
+pub(crate) fn retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT | StatusCode::CONFLICT | StatusCode::TOO_MANY_REQUESTS
+    ) || status.is_server_error()
+}
+
+/// Reads the `retry-after-ms` header, in milliseconds, or the `retry-after` header, in seconds.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<f64>().ok())
+    };
+    let seconds = header("retry-after-ms")
+        .map(|milliseconds| milliseconds / 1000.0)
+        .or_else(|| header("retry-after"))?;
+
+    Duration::try_from_secs_f64(seconds)
+        .ok()
+        .filter(|retry_after| *retry_after <= MAX_RETRY_AFTER)
+}
+
+/// Returns a number in `0.0..1.0` that is random enough for jitter.
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::Model,
+        stub::{StubResponse, StubServer},
+        OpenAiClient,
+    };
+    use serde_json::json;
+
+    fn model() -> serde_json::Value {
+        json!({
+            "id": "text-davinci-003",
+            "created": 1669599635,
+            "owned_by": "openai-internal",
+            "permission": [],
+            "root": "text-davinci-003",
+            "parent": null,
+        })
+    }
+
+    fn rate_limit_error(code: &str) -> serde_json::Value {
+        json!({
+            "error": {
+                "message": "Rate limit reached.",
+                "type": "requests",
+                "param": null,
+                "code": code,
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn retries() {
+        let server = StubServer::start(vec![
+            StubResponse::text(503, "text/html", "<html>Service Unavailable</html>"),
+            StubResponse::json(429, rate_limit_error("rate_limit_exceeded"))
+                .header("retry-after-ms", "10"),
+            StubResponse::json(200, model()),
+        ])
+        .await;
+        let client = OpenAiClient::with_env("", |_| None)
+            .base_url(server.url())
+            .retry_policy(RetryPolicy::new(3).initial_backoff(Duration::from_millis(10)));
+
+        Model::from_with_client("text-davinci-003", &client)
+            .await
+            .unwrap();
+
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up() {
+        let error = json!({
+            "error": {
+                "message": "The server had an error while processing your request.",
+                "type": "server_error",
+                "param": null,
+                "code": null,
+            },
+        });
+        let server = StubServer::start(vec![StubResponse::json(500, error)]).await;
+        let client = OpenAiClient::with_env("", |_| None)
+            .base_url(server.url())
+            .retry_policy(RetryPolicy::new(2).initial_backoff(Duration::from_millis(10)));
+
+        assert!(matches!(
+            Model::from_with_client("text-davinci-003", &client).await,
+            Err(Error::Api {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                ..
+            })
+        ));
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_permanent_errors() {
+        let server = StubServer::start(vec![
+            StubResponse::json(429, rate_limit_error("insufficient_quota")),
+            StubResponse::json(200, model()),
+        ])
+        .await;
+        let client = OpenAiClient::with_env("", |_| None)
+            .base_url(server.url())
+            .retry_policy(RetryPolicy::new(3).initial_backoff(Duration::from_millis(10)));
+
+        assert!(Model::from_with_client("text-davinci-003", &client)
+            .await
+            .is_err());
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy::new(10)
+            .initial_backoff(Duration::from_secs(1))
+            .max_backoff(Duration::from_secs(4));
+        let mut headers = HeaderMap::new();
+
+        for (attempt, max) in [(1, 1), (2, 2), (3, 4), (6, 4)] {
+            let backoff = policy.backoff(attempt, None);
+
+            assert!(backoff >= Duration::from_secs(max) / 2);
+            assert!(backoff <= Duration::from_secs(max));
+        }
+
+        headers.insert("retry-after", "3".parse().unwrap());
+
+        assert_eq!(policy.backoff(1, Some(&headers)), Duration::from_secs(3));
+
+        headers.insert("retry-after-ms", "250".parse().unwrap());
+
+        assert_eq!(
+            policy.backoff(1, Some(&headers)),
+            Duration::from_millis(250)
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! A tiny scripted HTTP server, so request handling can be tested without reaching the OpenAI API.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+#[derive(Clone, Debug)]
+pub struct StubResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub chunks: Vec<String>,
+}
+
+impl StubResponse {
+    pub fn json(status: u16, body: serde_json::Value) -> Self {
+        StubResponse {
+            status,
+            headers: vec![("content-type".to_string(), "application/json".to_string())],
+            chunks: vec![body.to_string()],
+        }
+    }
+
+    pub fn text(status: u16, content_type: &str, body: &str) -> Self {
+        StubResponse {
+            status,
+            headers: vec![("content-type".to_string(), content_type.to_string())],
+            chunks: vec![body.to_string()],
+        }
+    }
+
+    /// Sends every event as its own `data:` chunk, the way the API streams them.
+    pub fn event_stream(events: &[&str]) -> Self {
+        StubResponse {
+            status: 200,
+            headers: vec![("content-type".to_string(), "text/event-stream".to_string())],
+            chunks: events
+                .iter()
+                .map(|event| format!("data: {event}\n\n"))
+                .collect(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    /// Header names are lowercased.
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).unwrap()
+    }
+}
+
+pub struct StubServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl StubServer {
+    /// Starts a server answering requests with `responses` in order.
+    /// Once they run out, the last response is repeated.
+    pub async fn start(responses: Vec<StubResponse>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let responses = Arc::new(responses);
+
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(_) => return,
+                };
+                let recorded = recorded.clone();
+                let responses = responses.clone();
+
+                tokio::spawn(async move {
+                    serve(socket, recorded, responses).await;
+                });
+            }
+        });
+
+        StubServer { addr, requests }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}/v1/", self.addr)
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn serve(
+    mut socket: TcpStream,
+    recorded: Arc<Mutex<Vec<RecordedRequest>>>,
+    responses: Arc<Vec<StubResponse>>,
+) {
+    let Some(request) = read_request(&mut socket).await else {
+        return;
+    };
+    let response = {
+        let mut recorded = recorded.lock().unwrap();
+
+        recorded.push(request);
+
+        let index = (recorded.len() - 1).min(responses.len() - 1);
+
+        responses[index].clone()
+    };
+    let mut head = format!("HTTP/1.1 {} Stub\r\nconnection: close\r\n", response.status);
+
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+
+    head.push_str("\r\n");
+
+    if socket.write_all(head.as_bytes()).await.is_err() {
+        return;
+    }
+
+    for chunk in &response.chunks {
+        if socket.write_all(chunk.as_bytes()).await.is_err() {
+            return;
+        }
+
+        socket.flush().await.ok();
+
+        if response.chunks.len() > 1 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    socket.shutdown().await.ok();
+}
+
+async fn read_request(socket: &mut TcpStream) -> Option<RecordedRequest> {
+    let mut data = Vec::new();
+    let mut buffer = [0; 4096];
+    let head_end = loop {
+        if let Some(position) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position;
+        }
+
+        let read = socket.read(&mut buffer).await.ok()?;
+
+        if read == 0 {
+            return None;
+        }
+
+        data.extend_from_slice(&buffer[..read]);
+    };
+    let head = String::from_utf8_lossy(&data[..head_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+    let content_length: usize = headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    let mut body = data[head_end + 4..].to_vec();
+
+    while body.len() < content_length {
+        let read = socket.read(&mut buffer).await.ok()?;
+
+        if read == 0 {
+            break;
+        }
+
+        body.extend_from_slice(&buffer[..read]);
+    }
+
+    Some(RecordedRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).to_string(),
+    })
+}
@@ -0,0 +1,329 @@
+//! Counts tokens locally, with the byte pair encodings of OpenAI models.
+//!
+//! This module is only available with the `tokenizer` feature.
+//!
+//! ## Examples
+//!
+//! ```rust
+//! use openai::tokenizer::Encoding;
+//!
+//! let encoding = Encoding::for_model("gpt-3.5-turbo").unwrap();
+//! let tokens = encoding.encode("hello world");
+//!
+//! assert_eq!(tokens, [15339, 1917]);
+//! assert_eq!(encoding.decode(&tokens), "hello world");
+//! ```
+
+use crate::chat::ChatCompletionMessage;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use fancy_regex::Regex;
+use std::{collections::HashMap, sync::OnceLock};
+
+const CL100K_BASE_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+const R50K_BASE_PATTERN: &str =
+    r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+";
+
+const CL100K_BASE_SPECIAL_TOKENS: &[(&str, u32)] = &[
+    ("<|endoftext|>", 100257),
+    ("<|fim_prefix|>", 100258),
+    ("<|fim_middle|>", 100259),
+    ("<|fim_suffix|>", 100260),
+    ("<|endofprompt|>", 100276),
+];
+const R50K_BASE_SPECIAL_TOKENS: &[(&str, u32)] = &[("<|endoftext|>", 50256)];
+
+/// A byte pair encoding, which splits text into the tokens that models read and write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// Used by chat models and `text-embedding-ada-002`.
+    Cl100kBase,
+    /// Used by code models, `text-davinci-002` and `text-davinci-003`.
+    P50kBase,
+    /// Used by GPT-3 models like `davinci`.
+    R50kBase,
+}
+
+impl Encoding {
+    /// Returns the encoding used by `model`, if it is known.
+    ///
+    /// `gpt-4o` and later models use the `o200k_base` encoding, which isn't supported, so they return `None`.
+    pub fn for_model(model: &str) -> Option<Self> {
+        const PREFIXES: &[(&str, Encoding)] = &[
+            ("gpt-4-", Encoding::Cl100kBase),
+            ("gpt-3.5-turbo", Encoding::Cl100kBase),
+            ("gpt-35-turbo", Encoding::Cl100kBase),
+            ("text-embedding-", Encoding::Cl100kBase),
+            ("text-davinci-002", Encoding::P50kBase),
+            ("text-davinci-003", Encoding::P50kBase),
+            ("text-davinci-edit-", Encoding::P50kBase),
+            ("code-search-", Encoding::R50kBase),
+            ("code-", Encoding::P50kBase),
+            ("text-", Encoding::R50kBase),
+            ("davinci", Encoding::R50kBase),
+            ("curie", Encoding::R50kBase),
+            ("babbage", Encoding::R50kBase),
+            ("ada", Encoding::R50kBase),
+        ];
+
+        if model == "gpt-4" {
+            return Some(Encoding::Cl100kBase);
+        }
+
+        PREFIXES
+            .iter()
+            .find(|(prefix, _)| model.starts_with(prefix))
+            .map(|(_, encoding)| *encoding)
+    }
+
+    /// Splits `text` into tokens. Special tokens like `<|endoftext|>` are encoded like any other text.
+    pub fn encode(self, text: &str) -> Vec<u32> {
+        let bpe = self.bpe();
+        let mut tokens = Vec::new();
+
+        for piece in bpe.pattern.find_iter(text) {
+            let piece = piece
+                .expect("the pattern should not fail on any text")
+                .as_str();
+
+            match bpe.encoder.get(piece.as_bytes()) {
+                Some(token) => tokens.push(*token),
+                None => tokens.extend(bpe.merge(piece.as_bytes())),
+            }
+        }
+
+        tokens
+    }
+
+    /// Turns `tokens` back into text. Tokens that don't exist in this encoding are skipped,
+    /// and bytes that aren't valid UTF-8, for example because a character was split between tokens, are replaced.
+    pub fn decode(self, tokens: &[u32]) -> String {
+        String::from_utf8_lossy(&self.decode_bytes(tokens)).into_owned()
+    }
+
+    /// Turns `tokens` back into the bytes they stand for. Tokens that don't exist in this encoding are skipped.
+    pub fn decode_bytes(self, tokens: &[u32]) -> Vec<u8> {
+        let bpe = self.bpe();
+
+        tokens
+            .iter()
+            .filter_map(|token| bpe.decoder.get(token))
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// Counts the tokens of `text`.
+    pub fn count(self, text: &str) -> usize {
+        self.encode(text).len()
+    }
+
+    fn bpe(self) -> &'static Bpe {
+        static CL100K_BASE: OnceLock<Bpe> = OnceLock::new();
+        static P50K_BASE: OnceLock<Bpe> = OnceLock::new();
+        static R50K_BASE: OnceLock<Bpe> = OnceLock::new();
+
+        match self {
+            Encoding::Cl100kBase => CL100K_BASE.get_or_init(|| {
+                Bpe::new(
+                    include_str!("../assets/cl100k_base.tiktoken"),
+                    CL100K_BASE_SPECIAL_TOKENS,
+                    CL100K_BASE_PATTERN,
+                )
+            }),
+            Encoding::P50kBase => P50K_BASE.get_or_init(|| {
+                Bpe::new(
+                    include_str!("../assets/p50k_base.tiktoken"),
+                    R50K_BASE_SPECIAL_TOKENS,
+                    R50K_BASE_PATTERN,
+                )
+            }),
+            Encoding::R50kBase => R50K_BASE.get_or_init(|| {
+                Bpe::new(
+                    include_str!("../assets/r50k_base.tiktoken"),
+                    R50K_BASE_SPECIAL_TOKENS,
+                    R50K_BASE_PATTERN,
+                )
+            }),
+        }
+    }
+}
+
+/// Counts the tokens that `messages` take up in the prompt of a chat completion with `model`,
+/// including the tokens added around every message and those that start the reply.
+///
+/// Returns `None` if the encoding of `model` isn't known.
+/// Functions and tools that the model can call also take up tokens, which aren't counted.
+pub fn count_chat_tokens(model: &str, messages: &[ChatCompletionMessage]) -> Option<usize> {
+    let encoding = Encoding::for_model(model)?;
+    // `gpt-3.5-turbo-0301` adds a token per message, but none for the role when a name is given
+    let (tokens_per_message, tokens_per_name): (usize, isize) =
+        if model.starts_with("gpt-3.5-turbo-0301") {
+            (4, -1)
+        } else {
+            (3, 1)
+        };
+    let mut tokens = 3;
+
+    for message in messages {
+        tokens += tokens_per_message;
+        tokens += encoding.count(
+            serde_json::to_value(message.role)
+                .unwrap()
+                .as_str()
+                .unwrap_or_default(),
+        );
+        tokens += message
+            .content
+            .as_deref()
+            .map_or(0, |content| encoding.count(content));
+
+        if let Some(name) = &message.name {
+            tokens = tokens.saturating_add_signed(tokens_per_name) + encoding.count(name);
+        }
+
+        for call in &message.tool_calls {
+            tokens +=
+                encoding.count(&call.function.name) + encoding.count(&call.function.arguments);
+        }
+
+        if let Some(call) = &message.function_call {
+            tokens += encoding.count(&call.name) + encoding.count(&call.arguments);
+        }
+    }
+
+    Some(tokens)
+}
+
+struct Bpe {
+    encoder: HashMap<Vec<u8>, u32>,
+    decoder: HashMap<u32, Vec<u8>>,
+    pattern: Regex,
+}
+
+impl Bpe {
+    fn new(ranks: &str, special_tokens: &[(&str, u32)], pattern: &str) -> Self {
+        let encoder: HashMap<Vec<u8>, u32> = ranks
+            .lines()
+            .filter_map(|line| {
+                let (token, rank) = line.split_once(' ')?;
+
+                Some((STANDARD.decode(token).ok()?, rank.parse().ok()?))
+            })
+            .collect();
+        let decoder = encoder
+            .iter()
+            .map(|(bytes, rank)| (*rank, bytes.clone()))
+            .chain(
+                special_tokens
+                    .iter()
+                    .map(|(token, rank)| (*rank, token.as_bytes().to_vec())),
+            )
+            .collect();
+
+        Bpe {
+            encoder,
+            decoder,
+            pattern: Regex::new(pattern).expect("the pattern should be valid"),
+        }
+    }
+
+    /// Encodes a piece of text that isn't a token itself, by merging the pair of parts with the lowest rank until none can be merged.
+    fn merge(&self, piece: &[u8]) -> Vec<u32> {
+        // The boundaries between the parts of the piece, which are single bytes at first
+        let mut boundaries: Vec<usize> = (0..=piece.len()).collect();
+        let rank = |boundaries: &[usize], i: usize| {
+            boundaries
+                .get(i + 2)
+                .and_then(|end| self.encoder.get(&piece[boundaries[i]..*end]))
+                .copied()
+        };
+        let mut ranks: Vec<Option<u32>> = (0..boundaries.len() - 1)
+            .map(|i| rank(&boundaries, i))
+            .collect();
+
+        while let Some((i, _)) = ranks
+            .iter()
+            .enumerate()
+            .filter_map(|(i, rank)| rank.map(|rank| (i, rank)))
+            .min_by_key(|(_, rank)| *rank)
+        {
+            boundaries.remove(i + 1);
+            ranks.remove(i + 1);
+            ranks[i] = rank(&boundaries, i);
+
+            if i > 0 {
+                ranks[i - 1] = rank(&boundaries, i - 1);
+            }
+        }
+
+        boundaries
+            .windows(2)
+            .map(|part| self.encoder[&piece[part[0]..part[1]]])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::ChatCompletionMessageRole;
+
+    #[test]
+    fn encodings() {
+        assert_eq!(
+            Encoding::Cl100kBase.encode("tiktoken is great!"),
+            [83, 1609, 5963, 374, 2294, 0]
+        );
+        assert_eq!(Encoding::R50kBase.encode("hello world"), [31373, 995]);
+        assert_eq!(
+            Encoding::P50kBase.encode("    indented"),
+            [50258, 773, 4714]
+        );
+        assert_eq!(Encoding::Cl100kBase.count("<|endoftext|>"), 7);
+
+        for encoding in [Encoding::Cl100kBase, Encoding::P50kBase, Encoding::R50kBase] {
+            let text = "Ünïcödé, emoji 🦀 and\n\n  whitespace   ";
+
+            assert_eq!(encoding.decode(&encoding.encode(text)), text);
+        }
+
+        assert_eq!(Encoding::Cl100kBase.decode(&[100257]), "<|endoftext|>");
+    }
+
+    #[test]
+    fn models() {
+        assert_eq!(
+            Encoding::for_model("gpt-4-0613"),
+            Some(Encoding::Cl100kBase)
+        );
+        assert_eq!(
+            Encoding::for_model("text-davinci-003"),
+            Some(Encoding::P50kBase)
+        );
+        assert_eq!(Encoding::for_model("davinci"), Some(Encoding::R50kBase));
+        assert_eq!(Encoding::for_model("gpt-4o"), None);
+        assert_eq!(Encoding::for_model("gpt-4o-mini"), None);
+    }
+
+    #[test]
+    fn chat_tokens() {
+        let messages = [
+            ChatCompletionMessage::new(
+                ChatCompletionMessageRole::System,
+                "You are a helpful assistant.",
+            ),
+            ChatCompletionMessage::new(ChatCompletionMessageRole::User, "Hello!"),
+        ];
+
+        // 3 tokens for the reply, and 3 per message plus their role and content
+        assert_eq!(
+            count_chat_tokens("gpt-3.5-turbo", &messages),
+            Some(3 + 3 + 1 + 6 + 3 + 1 + 2)
+        );
+        assert_eq!(
+            count_chat_tokens("gpt-3.5-turbo-0301", &messages),
+            Some(3 + 4 + 1 + 6 + 4 + 1 + 2)
+        );
+        assert_eq!(count_chat_tokens("unknown", &messages), None);
+    }
+}
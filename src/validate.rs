@@ -0,0 +1,356 @@
+use crate::LogitBias;
+
+/// The most stop sequences a request may have.
+const MAX_STOP_SEQUENCES: usize = 4;
+
+/// Checks a request for mistakes that the API would reject, without sending it.
+///
+/// Requests are validated before they are sent, failing with [`Error::Validation`](crate::Error::Validation).
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationError>;
+}
+
+/// A mistake in a request, found by [`Validate`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    /// A parameter is outside of the range that the API accepts.
+    OutOfRange {
+        parameter: &'static str,
+        value: f64,
+        min: f64,
+        max: f64,
+    },
+    /// A parameter that must not be empty is empty, like the messages of a chat completion.
+    Empty { parameter: &'static str },
+    /// There are more stop sequences than the API accepts.
+    TooManyStopSequences { count: usize, max: usize },
+    /// `best_of` is not greater than `n`, so there is nothing to choose from.
+    BestOfNotGreaterThanN { best_of: u16, n: u16 },
+    /// The prompt and the tokens to generate don't fit in the context window of the model.
+    ///
+    /// The prompt is only counted with the `tokenizer` feature, `prompt_tokens` is 0 otherwise.
+    ContextLengthExceeded {
+        model: String,
+        prompt_tokens: u32,
+        max_tokens: u32,
+        context_window: u32,
+    },
+    /// The system prompt and the last message of a [`Conversation`](crate::chat::Conversation)
+    /// don't fit in its budget of tokens, so there is nothing left to truncate.
+    TokenBudgetExceeded { tokens: u32, token_budget: u32 },
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::OutOfRange {
+                parameter,
+                value,
+                min,
+                max,
+            } => write!(f, "`{parameter}` is {value}, but must be from {min} to {max}"),
+            ValidationError::Empty { parameter } => write!(f, "`{parameter}` must not be empty"),
+            ValidationError::TooManyStopSequences { count, max } => {
+                write!(f, "{count} stop sequences were given, but at most {max} are allowed")
+            }
+            ValidationError::BestOfNotGreaterThanN { best_of, n } => {
+                write!(f, "`best_of` is {best_of}, but must be greater than `n`, which is {n}")
+            }
+            ValidationError::ContextLengthExceeded {
+                model,
+                prompt_tokens,
+                max_tokens,
+                context_window,
+            } => write!(
+                f,
+                "{prompt_tokens} prompt tokens and {max_tokens} tokens to generate exceed the context window of {model}, which is {context_window} tokens"
+            ),
+            ValidationError::TokenBudgetExceeded {
+                tokens,
+                token_budget,
+            } => write!(
+                f,
+                "the last message and the system prompt use {tokens} tokens, but the token budget is {token_budget}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Returns how many tokens `model` can handle, including those it generates, if it is known.
+///
+/// Models are matched by their exact name, optionally followed by a dated snapshot such as `-0613` or `-2024-08-06`,
+/// so that newer models sharing a prefix with a known one, like `gpt-4.1`, are not checked against the wrong window.
+pub(crate) fn context_window(model: &str) -> Option<u32> {
+    // Snapshots whose window differs from their alias come before it.
+    const MODELS: &[(&str, u32)] = &[
+        ("gpt-4o", 128_000),
+        ("gpt-4o-mini", 128_000),
+        ("gpt-4-turbo", 128_000),
+        ("gpt-4-turbo-preview", 128_000),
+        ("gpt-4-1106-preview", 128_000),
+        ("gpt-4-0125-preview", 128_000),
+        ("gpt-4-vision-preview", 128_000),
+        ("gpt-4-1106-vision-preview", 128_000),
+        ("gpt-4-32k", 32_768),
+        ("gpt-4", 8_192),
+        ("gpt-3.5-turbo-0301", 4_096),
+        ("gpt-3.5-turbo-0613", 4_096),
+        ("gpt-3.5-turbo", 16_385),
+        ("gpt-3.5-turbo-16k", 16_385),
+        ("gpt-3.5-turbo-instruct", 4_096),
+        ("davinci-002", 16_384),
+        ("babbage-002", 16_384),
+        ("text-davinci-002", 4_097),
+        ("text-davinci-003", 4_097),
+        ("code-davinci-002", 8_001),
+        ("text-davinci-001", 2_049),
+        ("text-curie-001", 2_049),
+        ("text-babbage-001", 2_049),
+        ("text-ada-001", 2_049),
+        ("davinci", 2_049),
+        ("curie", 2_049),
+        ("babbage", 2_049),
+        ("ada", 2_049),
+    ];
+
+    let is_snapshot = |suffix: &str| {
+        suffix.strip_prefix('-').is_some_and(|date| {
+            !date.is_empty() && date.chars().all(|c| c.is_ascii_digit() || c == '-')
+        })
+    };
+
+    MODELS
+        .iter()
+        .find(|(name, _)| {
+            model
+                .strip_prefix(name)
+                .is_some_and(|suffix| suffix.is_empty() || is_snapshot(suffix))
+        })
+        .map(|(_, context_window)| *context_window)
+}
+
+pub(crate) fn check_range(
+    parameter: &'static str,
+    value: Option<impl Into<f64>>,
+    min: f64,
+    max: f64,
+) -> Result<(), ValidationError> {
+    match value.map(Into::into) {
+        Some(value) if !(min..=max).contains(&value) => Err(ValidationError::OutOfRange {
+            parameter,
+            value,
+            min,
+            max,
+        }),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn check_stop(stop: &[String]) -> Result<(), ValidationError> {
+    if stop.len() > MAX_STOP_SEQUENCES {
+        return Err(ValidationError::TooManyStopSequences {
+            count: stop.len(),
+            max: MAX_STOP_SEQUENCES,
+        });
+    }
+
+    Ok(())
+}
+
+pub(crate) fn check_logit_bias(logit_bias: &LogitBias) -> Result<(), ValidationError> {
+    match logit_bias.out_of_range() {
+        Some((_, bias)) => Err(ValidationError::OutOfRange {
+            parameter: "logit_bias",
+            value: bias.into(),
+            min: -100.0,
+            max: 100.0,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Checks that the prompt and the tokens to generate fit in the context window of `model`, if it is known.
+pub(crate) fn check_context(
+    model: &str,
+    prompt_tokens: u32,
+    max_tokens: u32,
+) -> Result<(), ValidationError> {
+    match context_window(model) {
+        Some(context_window) if prompt_tokens.saturating_add(max_tokens) > context_window => {
+            Err(ValidationError::ContextLengthExceeded {
+                model: model.to_string(),
+                prompt_tokens,
+                max_tokens,
+                context_window,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chat::{ChatCompletion, ChatCompletionMessage, ChatCompletionMessageRole},
+        completions::Completion,
+        Error, OpenAiClient,
+    };
+
+    #[test]
+    fn completions() {
+        let valid = || Completion::builder("text-davinci-003").prompt("Hello");
+
+        assert_eq!(valid().build().unwrap().validate(), Ok(()));
+        assert_eq!(
+            valid().temperature(2.5).build().unwrap().validate(),
+            Err(ValidationError::OutOfRange {
+                parameter: "temperature",
+                value: 2.5,
+                min: 0.0,
+                max: 2.0
+            })
+        );
+        assert!(matches!(
+            valid().presence_penalty(-2.5).build().unwrap().validate(),
+            Err(ValidationError::OutOfRange {
+                parameter: "presence_penalty",
+                ..
+            })
+        ));
+        assert_eq!(
+            valid()
+                .stop(vec!["a".to_string(); 5])
+                .build()
+                .unwrap()
+                .validate(),
+            Err(ValidationError::TooManyStopSequences { count: 5, max: 4 })
+        );
+        assert_eq!(
+            valid().best_of(2u16).n(2u16).build().unwrap().validate(),
+            Err(ValidationError::BestOfNotGreaterThanN { best_of: 2, n: 2 })
+        );
+        assert!(matches!(
+            valid().max_tokens(5000).build().unwrap().validate(),
+            Err(ValidationError::ContextLengthExceeded {
+                context_window: 4097,
+                ..
+            })
+        ));
+        assert_eq!(
+            Completion::builder("my-fine-tune")
+                .max_tokens(50000)
+                .build()
+                .unwrap()
+                .validate(),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn context_windows() {
+        assert_eq!(context_window("gpt-3.5-turbo"), Some(16_385));
+        assert_eq!(context_window("gpt-3.5-turbo-0613"), Some(4_096));
+        assert_eq!(context_window("gpt-3.5-turbo-0125"), Some(16_385));
+        assert_eq!(context_window("gpt-4"), Some(8_192));
+        assert_eq!(context_window("gpt-4-0613"), Some(8_192));
+        assert_eq!(context_window("gpt-4-1106-preview"), Some(128_000));
+        assert_eq!(context_window("gpt-4o-2024-08-06"), Some(128_000));
+        assert_eq!(context_window("gpt-4o-mini"), Some(128_000));
+        assert_eq!(context_window("text-davinci-003"), Some(4_097));
+        // Unknown models, even with a known prefix, are not checked
+        assert_eq!(context_window("gpt-4.1"), None);
+        assert_eq!(context_window("gpt-4.5-preview"), None);
+        assert_eq!(context_window("text-davinci-edit-001"), None);
+        assert_eq!(context_window("text-embedding-3-small"), None);
+        assert_eq!(context_window("text-moderation-latest"), None);
+
+        let message = ChatCompletionMessage::new(ChatCompletionMessageRole::User, "Hello!");
+
+        assert_eq!(
+            ChatCompletion::builder("gpt-3.5-turbo", [message])
+                .max_tokens(8000u64)
+                .build()
+                .unwrap()
+                .validate(),
+            Ok(())
+        );
+    }
+
+    #[tokio::test]
+    async fn chat_completions() {
+        let message = ChatCompletionMessage::new(ChatCompletionMessageRole::User, "Hello!");
+
+        assert_eq!(
+            ChatCompletion::builder("gpt-3.5-turbo", Vec::new())
+                .build()
+                .unwrap()
+                .validate(),
+            Err(ValidationError::Empty {
+                parameter: "messages"
+            })
+        );
+
+        // Invalid requests fail before they are sent
+        let client = OpenAiClient::new("key").base_url("http://127.0.0.1:1/v1/");
+        let result = ChatCompletion::builder("gpt-3.5-turbo", [message])
+            .top_p(1.5)
+            .client(&client)
+            .create()
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(Error::Validation(ValidationError::OutOfRange {
+                parameter: "top_p",
+                ..
+            }))
+        ));
+    }
+
+    #[cfg(feature = "tokenizer")]
+    #[test]
+    fn uncounted_prompts() {
+        // `gpt-4o` uses an encoding that isn't supported, so only `max_tokens` is checked
+        let message = ChatCompletionMessage::new(ChatCompletionMessageRole::User, "Hello!");
+        let request = |max_tokens: u64| {
+            ChatCompletion::builder("gpt-4o", [message.clone()])
+                .max_tokens(max_tokens)
+                .build()
+                .unwrap()
+        };
+
+        assert_eq!(request(128_000).validate(), Ok(()));
+        assert_eq!(
+            request(128_001).validate(),
+            Err(ValidationError::ContextLengthExceeded {
+                model: "gpt-4o".to_string(),
+                prompt_tokens: 0,
+                max_tokens: 128_001,
+                context_window: 128_000,
+            })
+        );
+    }
+
+    #[cfg(feature = "tokenizer")]
+    #[test]
+    fn counted_prompts() {
+        let request = Completion::builder("text-davinci-003")
+            .prompt("hello world")
+            .max_tokens(4096)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            request.validate(),
+            Err(ValidationError::ContextLengthExceeded {
+                model: "text-davinci-003".to_string(),
+                prompt_tokens: 2,
+                max_tokens: 4096,
+                context_window: 4097,
+            })
+        );
+    }
+}